@@ -4,10 +4,58 @@ defmodule Rox do
 
   """
 
-  alias __MODULE__.{DB, ColumnFamily, Native, Utils, Cursor, Snapshot}
+  alias __MODULE__.{DB, ColumnFamily, Native, Utils, Cursor, Snapshot, Cache, WriteBufferManager}
 
+  @typedoc """
+  The compaction style of a database or column family.
+
+  `:none` turns off automatic compactions, leaving only manual ones through `compact_range/4`.
+  Combining it with `disable_auto_compactions: false` is rejected as an invalid option.
+  """
   @type compaction_style :: :level | :universal | :fifo | :none
-  @type compression_type :: :snappy | :zlib | :bzip2 | :lz4 | :lz4h | :none
+
+  @typedoc """
+  Options of the `:universal` compaction style.
+
+    * `:size_ratio` - the percentage by which a sorted run may be larger than the next ones for
+      them to still be compacted together.
+    * `:min_merge_width` / `:max_merge_width` - the minimum / maximum number of sorted runs to
+      compact at once.
+    * `:max_size_amplification_percent` - the amount of extra space, as a percentage of the data
+      size, after which every sorted run is compacted into one.
+  """
+  @type universal_compaction_options :: [
+    {:size_ratio, non_neg_integer} |
+    {:min_merge_width, pos_integer} |
+    {:max_merge_width, pos_integer} |
+    {:max_size_amplification_percent, non_neg_integer}
+  ]
+
+  @typedoc """
+  Options of the `:fifo` compaction style, which drops the oldest SST files outright.
+
+    * `:max_table_files_size` - the total size of SST files, in bytes, past which the oldest ones
+      are dropped.
+    * `:ttl` - the age, in seconds, past which SST files are dropped.
+  """
+  @type fifo_compaction_options :: [
+    {:max_table_files_size, pos_integer} |
+    {:ttl, pos_integer}
+  ]
+  @type compression_type :: :snappy | :zlib | :bzip2 | :lz4 | :lz4h | :zstd | :none
+
+  @typedoc """
+  Options of the compression library.
+
+    * `:level` - the compression level, such as `1` to `22` for `:zstd`. Defaults to the
+      library's own default.
+    * `:max_dict_bytes` - the maximum size of the dictionary trained on each SST file (for
+      `:zstd` and `:zlib`). Defaults to `0`, which disables dictionaries.
+  """
+  @type compression_options :: [
+    {:level, integer} |
+    {:max_dict_bytes, non_neg_integer}
+  ]
 
   @type key :: String.t | binary
   @type value :: any
@@ -16,17 +64,61 @@ defmodule Rox do
 
   @type file_path :: String.t
 
-
+  @typedoc "The name of a RocksDB property, such as `\"rocksdb.estimate-num-keys\"`"
+  @type property :: String.t
+
+
+  @typedoc "A column family to open, optionally with its own options"
+  @type cf_descriptor :: ColumnFamily.name | {ColumnFamily.name, cf_options}
+
+  @typedoc """
+  Options of the block based table format, which RocksDB stores its SST files in.
+
+    * `:block_size` - the size of the blocks which data is read and cached in.
+    * `:block_cache_size` - the capacity of the LRU cache of uncompressed blocks, in bytes. Each
+      `open/3` creates one such cache, which the column families that inherit the database's
+      options share. Column families with options of their own, including those created with
+      `create_cf/3`, get a cache of their own.
+    * `:block_cache` - a `Rox.Cache` to use as the block cache, which can be shared with other
+      databases and column families.
+    * `:no_block_cache` - disables the block cache altogether.
+    * `:bloom_filter_policy` - adds a bloom filter with the given number of bits per key to every
+      SST file, which lets point lookups skip the files that don't contain the key.
+    * `:cache_index_and_filter_blocks` - keeps index and filter blocks in the block cache rather
+      than in memory for as long as the file is open.
+    * `:format_version` - the version of the table format to write.
+  """
   @type block_based_table_options :: [
     {:no_block_cache, boolean} |
     {:block_size, pos_integer} |
     {:block_cache_size, pos_integer} |
+    {:block_cache, Cache.t} |
     {:bloom_filter_policy, bits_per_key :: pos_integer} |
-    {:format_version, 0 | 1 | 2} |
-    {:skip_table_builder_flush, boolean} |
+    {:format_version, 0 | 1 | 2 | 3 | 4} |
     {:cache_index_and_filter_blocks, boolean}
   ]
 
+  @typedoc """
+  Extracts the prefix used by prefix bloom filters and `stream_prefix/3` from each key.
+
+  `{:fixed, len}` uses the first `len` bytes of every key, and ignores keys shorter than `len`.
+  `{:capped, len}` uses up to the first `len` bytes, so shorter keys are their own prefix.
+  """
+  @type prefix_extractor :: {:fixed, pos_integer} | {:capped, pos_integer}
+
+  @typedoc """
+  A native merge operator, used to combine the values written with `merge/4`.
+
+    * `:int64_add` - values are integers which are added together, starting from `0`.
+    * `:append` / `{:append, delimiter}` - binary values are concatenated, with `delimiter`
+      between each value.
+    * `:term_set_union` - values are lists of terms which are merged into a single sorted list
+      without duplicates, like `:ordsets.union/2`.
+    * `:max` / `:min` - the greatest / smallest value is kept, as compared by the Erlang term
+      order.
+  """
+  @type merge_operator :: :int64_add | :append | {:append, binary} | :term_set_union | :max | :min
+
   @type access_hint :: :normal | :sequential | :willneed | :none
   @type wal_recovery_mode ::
     :tolerate_corrupted_tail_records |
@@ -34,46 +126,110 @@ defmodule Rox do
     :point_in_time_recovery |
     :skip_any_corrupted_records
 
-  @type db_options :: [
-    {:total_threads, pos_integer} |
+  @typedoc "Options which apply to each column family, see `create_cf/3`."
+  @type cf_option ::
     {:optimize_level_type_compaction_memtable_memory_budget, integer} |
-    {:auto_create_column_families, boolean} |
-    {:create_if_missing, boolean} |
-    {:max_open_files, pos_integer} |
     {:compression_type, compression_type} |
-    {:use_fsync, boolean} |
-    {:bytes_per_sync, pos_integer} |
-    {:allow_os_buffer, boolean} |
-    {:table_cache_num_shard_bits, pos_integer} |
+    {:compression_per_level, [compression_type]} |
+    {:compression_options, compression_options} |
+    {:bottommost_compression_type, compression_type} |
+    {:bottommost_compression_options, compression_options} |
     {:min_write_buffer_number, pos_integer} |
     {:max_write_buffer_number, pos_integer} |
     {:write_buffer_size, pos_integer} |
     {:max_bytes_for_level_base, pos_integer} |
     {:max_bytes_for_level_multiplier, pos_integer} |
-    {:max_manifest_file_size, pos_integer} |
     {:target_file_size_base, pos_integer} |
     {:min_write_buffer_number_to_merge, pos_integer} |
     {:level_zero_file_num_compaction_trigger, non_neg_integer} |
     {:level_zero_slowdown_writes_trigger, non_neg_integer} |
     {:level_zero_stop_writes_trigger, non_neg_integer} |
     {:compaction_style, compaction_style} |
-    {:max_background_compactions, pos_integer} |
-    {:max_background_flushes, pos_integer} |
+    {:universal_compaction_options, universal_compaction_options} |
+    {:fifo_compaction_options, fifo_compaction_options} |
     {:disable_auto_compactions, boolean} |
     {:report_bg_io_stats, boolean} |
     {:num_levels, pos_integer} |
-    {:use_direct_io_for_flush_and_compaction, boolean}
+    {:prefix_extractor, prefix_extractor} |
+    {:memtable_prefix_bloom_ratio, float} |
+    {:merge_operator, merge_operator} |
+    {:block_based_table_options, block_based_table_options}
+
+  @type cf_options :: [cf_option]
+
+  @typedoc "Options which apply to the database as a whole, and can't be set per column family."
+  @type db_wide_option ::
+    {:total_threads, pos_integer} |
+    {:auto_create_column_families, boolean} |
+    {:create_if_missing, boolean} |
+    {:max_open_files, pos_integer} |
+    {:use_fsync, boolean} |
+    {:bytes_per_sync, pos_integer} |
+    {:allow_os_buffer, boolean} |
+    {:table_cache_num_shard_bits, pos_integer} |
+    {:max_manifest_file_size, pos_integer} |
+    {:max_background_compactions, pos_integer} |
+    {:max_background_flushes, pos_integer} |
+    {:use_direct_io_for_flush_and_compaction, boolean} |
+    {:write_buffer_manager, WriteBufferManager.t}
+
+  @typedoc """
+  The options of a database, which also serve as the options of each column family which isn't
+  given options of its own (see `open/3`).
+  """
+  @type db_options :: [db_wide_option | cf_option]
+
+  @type compact_options :: [
+    {:exclusive_manual_compaction, boolean} |
+    {:change_level, boolean} |
+    {:target_level, integer} |
+    {:bottommost_level_compaction, :skip | :if_have_compaction_filter | :force} |
+    {:notify, boolean}
   ]
 
+  @type read_tier :: :all | :block_cache | :persisted | :memtable
+
   @type read_options :: [
     {:fill_cache, boolean} |
-    {:iterate_upper_bound, binary}
+    {:verify_checksums, boolean} |
+    {:iterate_upper_bound, binary} |
+    {:iterate_lower_bound, binary} |
+    {:read_tier, read_tier} |
+    {:prefix_same_as_start, boolean} |
+    {:total_order_seek, boolean}
     # {:snapshot, snapshot_handle} |
   ]
 
+  @typedoc "Options accepted by the streaming functions, in addition to the `read_options`"
+  @type stream_options :: [{:chunk_size, pos_integer}]
+
+  @cursor_options [:chunk_size]
+
+  # Options which are themselves lists of options
+  @nested_options [
+    :block_based_table_options,
+    :universal_compaction_options,
+    :fifo_compaction_options,
+    :compression_options,
+    :bottommost_compression_options
+  ]
+
+  @typedoc """
+  Options of a single write or batch.
+
+    * `:sync` - syncs the write-ahead log to disk before the write returns.
+    * `:disable_wal` - skips the write-ahead log, so the write is lost if the process crashes
+      before the memtable is flushed.
+    * `:no_slowdown` - fails with `{:error, :incomplete}` rather than waiting if the write would
+      be stalled. See `write_stall_status/1`.
+    * `:low_pri` - marks the write as low priority, slowing it down rather than the writes of
+      higher priority when compactions fall behind.
+  """
   @type write_options :: [
     {:sync, boolean} |
-    {:disable_wal, boolean}
+    {:disable_wal, boolean} |
+    {:no_slowdown, boolean} |
+    {:low_pri, boolean}
   ]
 
   @doc """
@@ -82,41 +238,67 @@ defmodule Rox do
   If `column_families` are provided, a 3 element tuple will be returned with
   the second element being a map of column family names to `Rox.ColumnFamily` handles.
   The column families must have already been created via `create_cf` or the option
-  `auto_create_column_families` can be set to `true`. If it is, the `db_opts` will be
-  used to create the column families.
+  `auto_create_column_families` can be set to `true`. If it is, any column families which don't
+  exist yet are created with the same options they're opened with.
+
+  Each column family is opened with the `db_opts`, unless it's given as a `{name, cf_opts}`
+  descriptor, in which case it gets its own options (such as its write buffer size, compression
+  or merge operator), just like with `create_cf/3`. RocksDB doesn't persist these options, so they
+  need to be passed every time the database is opened.
+
+  Passing `:all_column_families` instead of a list opens every column family which exists in
+  the database at `path` (as reported by `list_cf/2`), including `"default"`, and returns them in
+  the same map. This is useful when the column families of the database aren't known ahead of
+  time. If the database doesn't exist yet, only `"default"` is opened. Any other error listing
+  the column families is returned as is.
 
 
-  The database will automatically be closed when the BEAM VM releases it for garbage collection.
+  Unknown options, and options with values of the wrong type, are rejected with
+  `{:error, {:invalid_option, key, reason}}`, where `reason` is either `:unknown_option` or
+  `:invalid_value`. The same goes for the `write_options` of every write, the `read_options` of
+  every read and the `compact_options` of `compact_range/4`.
+
+  The database will automatically be closed when the BEAM VM releases it for garbage collection,
+  or it can be closed explicitly with `close/1`.
 
   """
-  @spec open(file_path, db_options, [ColumnFamily.name]) ::
+  @spec open(file_path, db_options, [cf_descriptor] | :all_column_families) ::
     {:ok, DB.t} |
     {:ok, DB.t, %{ColumnFamily.name => ColumnFamily.t}} |
     {:error, any}
-  def open(path, db_opts \\ [], column_families \\ []) when is_binary(path) and is_list(db_opts) and is_list(column_families) do
-    auto_create_cfs? =
-      db_opts[:auto_create_column_families]
-
+  def open(path, db_opts \\ [], column_families \\ [])
+  def open(path, db_opts, :all_column_families) when is_binary(path) and is_list(db_opts) do
+    # Every RocksDB database has a CURRENT file, without it there's nothing to discover
+    listed =
+      if File.exists?(Path.join(path, "CURRENT")) do
+        list_cf(path, db_opts)
+      else
+        {:ok, ["default"]}
+      end
+
+    with {:ok, column_families} <- listed do
+      open(path, db_opts, column_families)
+    end
+  end
+  def open(path, db_opts, column_families) when is_binary(path) and is_list(db_opts) and is_list(column_families) do
     case column_families do
       [] ->
         do_open_db_with_no_cf(path, db_opts)
 
       _ ->
-        # First try opening with existing column families
-        with {:ok, db}         <- Native.open(path, to_map(db_opts), column_families),
+        names = Enum.map(column_families, &cf_descriptor_name/1)
+        descriptors = Enum.map(column_families, &native_cf_descriptor/1)
+
+        # Missing column families are created by RocksDB itself with `auto_create_column_families`
+        with {:ok, db}         <- Native.open(path, to_map(db_opts), descriptors),
                    db          <- DB.wrap_resource(db),
-             {:ok, cf_handles} <- map_or_error(column_families, &cf_handle(db, &1)) do
+             {:ok, cf_handles} <- map_or_error(names, &cf_handle(db, &1)) do
 
             cf_map =
-              Enum.zip(column_families, cf_handles)
+              Enum.zip(names, cf_handles)
               |> Enum.into(%{})
 
             {:ok, db, cf_map}
-        else
-          {:error, << "Invalid argument: Column family not found:", _rest :: binary >>} when auto_create_cfs? ->
-            do_open_db_and_create_cfs(path, db_opts, column_families)
-          other ->
-           other
         end
     end
   end
@@ -127,16 +309,23 @@ defmodule Rox do
     end
   end
 
-  defp do_open_db_and_create_cfs(path, opts, column_families) do
-    with {:ok, db} <- do_open_db_with_no_cf(path, opts),
-         {:ok, cf_handles} <- map_or_error(column_families, &create_cf(db, &1, opts)) do
+  defp cf_descriptor_name({name, _cf_opts}), do: name
+  defp cf_descriptor_name(name), do: name
 
-      cf_map =
-        Enum.zip(column_families, cf_handles)
-        |> Enum.into(%{})
+  defp native_cf_descriptor({name, cf_opts}), do: {name, to_map(cf_opts)}
+  defp native_cf_descriptor(name), do: name
 
-      {:ok, db, cf_map}
-    end
+  @doc """
+  Flushes and closes the database, releasing its lock on `path` so that it can be reopened.
+
+  Any iterators and snapshots of the database are released as well. From then on, every operation
+  on the database, or on any column family, cursor or snapshot derived from it, returns
+  `{:error, :closed}`. Closing a database which is already closed is a no-op.
+
+  """
+  @spec close(DB.t) :: :ok | {:error, any}
+  def close(%DB{resource: db}) do
+    Native.close(db)
   end
 
   @doc """
@@ -156,14 +345,32 @@ defmodule Rox do
   @doc """
   Create a column family in `db` with `name` and `opts`.
 
+  Options which apply to the database as a whole (see `db_wide_option`) can't be set per column
+  family, and are rejected with `{:error, {:invalid_option, key, :unknown_option}}`.
+
   """
-  @spec create_cf(DB.t, ColumnFamily.name, db_options) :: {:ok, ColumnFamily.t} | {:error, any}
+  @spec create_cf(DB.t, ColumnFamily.name, cf_options) :: {:ok, ColumnFamily.t} | {:error, any}
   def create_cf(%DB{resource: raw_db} = db, name, opts \\ []) do
     with {:ok, result} <- Native.create_cf(raw_db, name, to_map(opts)) do
       {:ok, ColumnFamily.wrap_resource(db, result, name)}
     end
   end
 
+  @doc """
+  Drops the column family with the given name from the database, along with all of its data.
+
+  Any `ColumnFamily.t` handles for the dropped column family, including those of snapshots,
+  return `{:error, :column_family_dropped}` from then on.
+
+  """
+  @spec drop_cf(DB.t, ColumnFamily.name | ColumnFamily.t) :: :ok | {:error, any}
+  def drop_cf(%DB{resource: raw_db}, name) when is_binary(name) do
+    Native.drop_cf(raw_db, name)
+  end
+  def drop_cf(%DB{} = db, %ColumnFamily{name: name}) do
+    drop_cf(db, name)
+  end
+
   @doc """
   Gets an existing `ColumnFamily.t` from the database or snapshot.
 
@@ -210,6 +417,31 @@ defmodule Rox do
     Native.put_cf(db, cf, key, Utils.encode(value), to_map(write_opts))
 
 
+  @doc """
+  Merges `value` into the existing value of `key` in the specified database or column family,
+  using the `merge_operator` that the database or column family was opened with.
+
+  Optionally takes a list of `write_options`.
+
+  As with `put/4`, non-binary values will automatically be encoded using the
+  `:erlang.term_to_binary/1` function.
+
+  ## Examples
+
+      {:ok, db} = Rox.open(path, create_if_missing: true, merge_operator: :int64_add)
+      :ok = Rox.merge(db, "page_views", 1)
+      :ok = Rox.merge(db, "page_views", 2)
+      {:ok, 3} = Rox.get(db, "page_views")
+
+  """
+  @spec merge(DB.t | ColumnFamily.t, key, value, write_options) :: :ok | {:error, any}
+  def merge(db_or_cf, key, value, write_opts \\ [])
+  def merge(%DB{resource: db}, key, value, write_opts) when is_binary(key) and is_list(write_opts), do:
+    Native.merge(db, key, Utils.encode(value), to_map(write_opts))
+  def merge(%ColumnFamily{db_resource: db, cf_resource: cf}, key, value, write_opts) when is_binary(key) and is_list(write_opts), do:
+    Native.merge_cf(db, cf, key, Utils.encode(value), to_map(write_opts))
+
+
   @doc """
   Get a key/value pair in the given column family of the given snapshot or database with the
   specified `key`.
@@ -237,12 +469,97 @@ defmodule Rox do
     |> Utils.decode
   end
 
+  @doc """
+  Gets the values of several `keys` at once from the given database, column family or snapshot.
+
+  Returns a list with one result per key, in the same order as `keys`, each of which is shaped
+  like the result of `get/3`. To look up keys across several column families, pass a database or
+  snapshot along with `{column_family, key}` tuples instead of plain keys. Plain keys may be mixed
+  in with the tuples, and are looked up in the default column family.
+
+  Optionally takes a list of `read_options`.
+
+  ## Examples
+
+      [{:ok, "Bob"}, :not_found] = Rox.multi_get(people, ["bob", "alice"])
+      [{:ok, "Bob"}, {:ok, 42}] = Rox.multi_get(db, [{people, "bob"}, {ages, "bob"}])
+
+  """
+  @spec multi_get(DB.t | ColumnFamily.t | Snapshot.t, [key | {ColumnFamily.t, key}], read_options)
+    :: [{:ok, value} | :not_found | {:error, any}]
+     | {:error, any}
+  def multi_get(db_snapshot_or_cf, keys, opts \\ [])
+  def multi_get(%DB{resource: db}, keys, opts) when is_list(keys) and is_list(opts) do
+    do_multi_get(db, keys, opts)
+  end
+  def multi_get(%Snapshot{resource: snapshot}, keys, opts) when is_list(keys) and is_list(opts) do
+    do_multi_get(snapshot, keys, opts)
+  end
+  def multi_get(%ColumnFamily{db_resource: db, cf_resource: cf}, keys, opts) when is_list(keys) and is_list(opts) do
+    Native.multi_get_cf(db, Enum.map(keys, &{cf, &1}), to_map(opts))
+    |> decode_multi_get
+  end
+
+  defp do_multi_get(resource, keys, opts) do
+    if Enum.all?(keys, &is_binary/1) do
+      Native.multi_get(resource, keys, to_map(opts))
+    else
+      cf_keys = Enum.map(keys, fn
+        {%ColumnFamily{cf_resource: cf}, key} when is_binary(key) -> {cf, key}
+        # Looked up in the default column family
+        key when is_binary(key) -> {nil, key}
+      end)
+      Native.multi_get_cf(resource, cf_keys, to_map(opts))
+    end
+    |> decode_multi_get
+  end
+
+  defp decode_multi_get(results) when is_list(results), do: Enum.map(results, &Utils.decode/1)
+  defp decode_multi_get(error), do: error
+
+  @doc """
+  Checks whether `key` exists in the given database, column family or snapshot.
+
+  The key's bloom filters are checked first, so most missing keys are answered without touching
+  disk. Keys which may exist are then looked up without copying their value, which makes this
+  cheaper than `get/3` for large values.
+
+  Optionally takes a list of `read_options`.
+
+  Returns `{:error, reason}` if the check fails, eg. `{:error, :closed}` once the database was
+  closed or `{:error, :column_family_dropped}` for a dropped column family. As errors are truthy,
+  match on `true` rather than using the result as a condition when the handle may be closed.
+
+  """
+  @spec exists?(DB.t | ColumnFamily.t | Snapshot.t, key, read_options) :: boolean | {:error, any}
+  def exists?(db_snapshot_or_cf, key, opts \\ []) when is_binary(key) and is_list(opts) do
+    do_exists(db_snapshot_or_cf, key, to_map(opts))
+  end
+
+  defp do_exists(%DB{resource: db}, key, opts), do: Native.exists(db, key, opts)
+  defp do_exists(%ColumnFamily{db_resource: db, cf_resource: cf}, key, opts), do:
+    Native.exists_cf(db, cf, key, opts)
+  defp do_exists(%Snapshot{resource: snapshot}, key, opts), do: Native.exists(snapshot, key, opts)
+
   @doc """
   Returns a `Cursor.t` which will iterate records from the provided database or
   column family.
 
   Optionally takes an `Rox.Cursor.mode`. Defaults to `:start`.
 
+  A `{:range, from, to}` mode iterates only the keys between `from` and `to`. By default `from`
+  is inclusive and `to` is exclusive, which can be changed by passing `{:inclusive, key}` or
+  `{:exclusive, key}` as either bound. The bounds are enforced by RocksDB itself, so iteration
+  stops as soon as the end of the range is reached.
+
+      Rox.stream(db, {:range, "2017-04-01", {:inclusive, "2017-04-30"}})
+
+  Optionally takes a list of `read_options`. Passing `fill_cache: false` is recommended for
+  bulk scans so that they don't evict frequently read blocks from the block cache.
+
+  The `:chunk_size` option controls how many records are fetched from RocksDB at a time, see
+  `Rox.Cursor`.
+
   The default arguments of this function is used for the `Enumerable` implementation
   for `DB` and `ColumnFamily` structs.
 
@@ -252,21 +569,27 @@ defmodule Rox do
   each others results. This may or may not be desired.
 
   """
-  @spec stream(DB.t | ColumnFamily.t, Rox.Cursor.mode) :: Cursor.t | {:error, any}
-  def stream(db_or_cf, mode \\ :start)
-  def stream(%DB{resource: db}, mode) do
-    with {:ok, resource} = Native.iterate(db, mode) do
-      Cursor.wrap_resource(resource, mode)
+  @spec stream(DB.t | ColumnFamily.t | Snapshot.t, Rox.Cursor.mode, read_options | stream_options) :: Cursor.t | {:error, any}
+  def stream(db_or_cf, mode \\ :start, opts \\ [])
+  def stream(%DB{resource: db}, mode, opts) when is_list(opts) do
+    {cursor_opts, read_opts} = Keyword.split(opts, @cursor_options)
+
+    with {:ok, resource} <- Native.iterate(db, mode, to_map(read_opts)) do
+      Cursor.wrap_resource(resource, mode, cursor_opts)
     end
   end
-  def stream(%ColumnFamily{db_resource: db, cf_resource: cf}, mode) do
-    with {:ok, resource} = Native.iterate_cf(db, cf, mode) do
-      Cursor.wrap_resource(resource, mode)
+  def stream(%ColumnFamily{db_resource: db, cf_resource: cf}, mode, opts) when is_list(opts) do
+    {cursor_opts, read_opts} = Keyword.split(opts, @cursor_options)
+
+    with {:ok, resource} <- Native.iterate_cf(db, cf, mode, to_map(read_opts)) do
+      Cursor.wrap_resource(resource, mode, cursor_opts)
     end
   end
-  def stream(%Snapshot{resource: snapshot}, mode) do
-    with {:ok, resource} <- Native.iterate(snapshot, mode) do
-      Cursor.wrap_resource(resource, mode)
+  def stream(%Snapshot{resource: snapshot}, mode, opts) when is_list(opts) do
+    {cursor_opts, read_opts} = Keyword.split(opts, @cursor_options)
+
+    with {:ok, resource} <- Native.iterate(snapshot, mode, to_map(read_opts)) do
+      Cursor.wrap_resource(resource, mode, cursor_opts)
     end
   end
 
@@ -274,7 +597,7 @@ defmodule Rox do
   @doc """
   Returns a `Cursor.t` which will iterate *keys* from the provided database or column family.
 
-  Optionally takes a `Rox.Cursor.mode`, which defaults to `:start`.
+  Optionally takes a `Rox.Cursor.mode`, which defaults to `:start`, and a list of `read_options`.
 
   Note: The result of `stream_keys` is a cursor which is *not* meant to be shared across processes.
   Iterating over the cursor will result in an internal state in RocksDB being modified.
@@ -282,26 +605,79 @@ defmodule Rox do
   each others results. This may or may not be desired.
 
   """
-  @spec stream_keys(DB.t | ColumnFamily.t, Rox.Cursor.mode) :: Cursor.t | {:error, any}
-  def stream_keys(db_or_cf, mode \\ :start)
-  def stream_keys(%DB{resource: db}, mode) do
-    with {:ok, resource} = Native.iterate(db, mode) do
-      Cursor.wrap_resource(resource, mode, decode_values: false)
+  @spec stream_keys(DB.t | ColumnFamily.t, Rox.Cursor.mode, read_options | stream_options) :: Cursor.t | {:error, any}
+  def stream_keys(db_or_cf, mode \\ :start, opts \\ [])
+  def stream_keys(%DB{resource: db}, mode, opts) when is_list(opts) do
+    {cursor_opts, read_opts} = Keyword.split(opts, @cursor_options)
+
+    with {:ok, resource} <- Native.iterate(db, mode, to_map(read_opts)) do
+      Cursor.wrap_resource(resource, mode, [{:decode_values, false} | cursor_opts])
       |> Stream.map(&elem(&1, 0))
     end
   end
-  def stream_keys(%ColumnFamily{db_resource: db, cf_resource: cf}, mode) do
-    with {:ok, resource} = Native.iterate_cf(db, cf, mode) do
-      Cursor.wrap_resource(resource, mode, decode_values: false)
+  def stream_keys(%ColumnFamily{db_resource: db, cf_resource: cf}, mode, opts) when is_list(opts) do
+    {cursor_opts, read_opts} = Keyword.split(opts, @cursor_options)
+
+    with {:ok, resource} <- Native.iterate_cf(db, cf, mode, to_map(read_opts)) do
+      Cursor.wrap_resource(resource, mode, [{:decode_values, false} | cursor_opts])
       |> Stream.map(&elem(&1, 0))
     end
   end
 
 
+  @doc """
+  Returns a `Cursor.t` which will iterate all records whose key starts with `prefix` from the
+  provided database, column family or snapshot.
+
+  The scan is bounded natively by the smallest key sorting after the prefix, and by default seeks
+  in total order, so any `prefix` works regardless of the `prefix_extractor` the database or
+  column family was opened with.
+
+  When `prefix` is at least as long as the prefix extracted from every key, pass
+  `prefix_same_as_start: true` to seek by prefix instead, which uses the memtable and SST prefix
+  bloom filters to skip data that can't contain the prefix. RocksDB doesn't define the results
+  for shorter prefixes (eg. `"ab"` with `prefix_extractor: {:fixed, 4}`).
+
+  Optionally takes a list of `read_options`.
+
+  """
+  @spec stream_prefix(DB.t | ColumnFamily.t | Snapshot.t, binary, read_options | stream_options) :: Cursor.t | {:error, any}
+  def stream_prefix(db_or_cf, prefix, opts \\ []) when is_binary(prefix) and is_list(opts) do
+    opts =
+      case prefix_upper_bound(prefix) do
+        nil -> opts
+        upper_bound -> Keyword.put_new(opts, :iterate_upper_bound, upper_bound)
+      end
+
+    opts =
+      if Keyword.get(opts, :prefix_same_as_start, false) do
+        opts
+      else
+        Keyword.put_new(opts, :total_order_seek, true)
+      end
+
+    stream(db_or_cf, {:from, prefix, :forward}, opts)
+  end
+
+  # The smallest key which sorts after every key starting with `prefix`, or `nil` if there is
+  # none (ie. the prefix is empty or consists of only `0xFF` bytes)
+  defp prefix_upper_bound(prefix) do
+    prefix
+    |> :binary.bin_to_list
+    |> :lists.reverse
+    |> Enum.drop_while(& &1 == 255)
+    |> case do
+      [] -> nil
+      [last | rest] -> :lists.reverse([last + 1 | rest]) |> :binary.list_to_bin
+    end
+  end
+
+
   @doc """
   Return the approximate number of keys in the database or specified column family.
 
-  Implemented by calling GetIntProperty with `rocksdb.estimate-num-keys`
+  Implemented by calling GetIntProperty with `rocksdb.estimate-num-keys`. Use
+  `count_exact/2` when an exact count is needed.
 
   """
   @spec count(DB.t | ColumnFamily.t) :: non_neg_integer | {:error, any}
@@ -313,6 +689,150 @@ defmodule Rox do
   end
 
 
+  @doc """
+  Return the exact number of keys in the database, column family or snapshot.
+
+  Optionally restricts the count to a `{:range, from, to}` (see `stream/3`) or to the keys
+  starting with a given `{:prefix, prefix}`.
+
+  The keys are counted by iterating over them inside RocksDB, without copying any keys or values
+  into Erlang terms. Even so, this takes time proportional to the number of keys being counted.
+  Databases and column families are counted from a snapshot taken when the count starts, so
+  writes made in the meantime aren't counted. A long count doesn't keep the database from being
+  closed (or the column family from being dropped), which makes it return an error instead.
+
+  """
+  @spec count_exact(DB.t | ColumnFamily.t | Snapshot.t, :all | {:range, Cursor.range_bound, Cursor.range_bound} | {:prefix, binary})
+    :: non_neg_integer
+     | {:error, any}
+  def count_exact(db_snapshot_or_cf, scope \\ :all)
+  def count_exact(%DB{resource: db}, scope) do
+    Native.count_exact(db, count_mode(scope))
+  end
+  def count_exact(%ColumnFamily{db_resource: db, cf_resource: cf}, scope) do
+    Native.count_exact_cf(db, cf, count_mode(scope))
+  end
+  def count_exact(%Snapshot{resource: snapshot}, scope) do
+    Native.count_exact(snapshot, count_mode(scope))
+  end
+
+  defp count_mode(:all), do: :start
+  defp count_mode({:range, _from, _to} = range), do: range
+  defp count_mode({:prefix, prefix}) when is_binary(prefix) do
+    case prefix_upper_bound(prefix) do
+      nil -> {:from, prefix, :forward}
+      upper_bound -> {:range, prefix, upper_bound}
+    end
+  end
+
+
+  @doc """
+  Manually compacts the keys from `start_key` to `end_key` (both inclusive) in the database or
+  column family. Passing `nil` for either key leaves that end of the range open, so
+  `compact_range(db)` compacts the whole database.
+
+  Optionally takes a list of `compact_options`:
+
+    * `:exclusive_manual_compaction` - when `true` (the RocksDB default), no other compactions
+      run concurrently with this one.
+    * `:change_level` / `:target_level` - moves the compacted files to `target_level`.
+    * `:bottommost_level_compaction` - whether the files in the bottommost level are
+      compacted too.
+    * `:notify` - when `true`, the compaction runs in the background, `{:ok, ref}` is returned
+      immediately and `{:rox_compaction_complete, ref}` is sent to the calling process once it
+      is done. Otherwise this function returns `:ok` once the compaction has completed.
+
+  Closing the database cancels any compaction which is still running.
+
+  """
+  @spec compact_range(DB.t | ColumnFamily.t, key | nil, key | nil, compact_options)
+    :: :ok
+     | {:ok, reference}
+     | {:error, any}
+  def compact_range(db_or_cf, start_key \\ nil, end_key \\ nil, opts \\ [])
+  def compact_range(%DB{resource: db}, start_key, end_key, opts) when is_list(opts) do
+    {notify, opts} = Keyword.pop(opts, :notify, false)
+
+    do_compact_range(notify, &Native.compact_range(db, start_key, end_key, to_map(opts), &1))
+  end
+  def compact_range(%ColumnFamily{db_resource: db, cf_resource: cf}, start_key, end_key, opts) when is_list(opts) do
+    {notify, opts} = Keyword.pop(opts, :notify, false)
+
+    do_compact_range(notify, &Native.compact_range_cf(db, cf, start_key, end_key, to_map(opts), &1))
+  end
+
+  defp do_compact_range(false, compact), do: compact.(nil)
+  defp do_compact_range(true, compact) do
+    ref = make_ref()
+
+    with :ok <- compact.(ref) do
+      {:ok, ref}
+    end
+  end
+
+
+  @doc """
+  Returns the value of the RocksDB property `name` for the database or column family.
+
+  Returns `:not_found` if the property is unknown to RocksDB.
+
+  ## Examples
+
+      {:ok, stats} = Rox.get_property(db, "rocksdb.stats")
+
+  """
+  @spec get_property(DB.t | ColumnFamily.t, property) :: {:ok, String.t} | :not_found | {:error, any}
+  def get_property(%DB{resource: db}, name) when is_binary(name) do
+    Native.get_property(db, name)
+  end
+  def get_property(%ColumnFamily{db_resource: db, cf_resource: cf}, name) when is_binary(name) do
+    Native.get_property_cf(db, cf, name)
+  end
+
+
+  @doc """
+  Returns the value of the integer RocksDB property `name` for the database or column family.
+
+  Returns `:not_found` if the property is unknown to RocksDB or isn't an integer property.
+
+  ## Examples
+
+      {:ok, bytes} = Rox.get_int_property(db, "rocksdb.cur-size-all-mem-tables")
+
+  """
+  @spec get_int_property(DB.t | ColumnFamily.t, property) :: {:ok, non_neg_integer} | :not_found | {:error, any}
+  def get_int_property(%DB{resource: db}, name) when is_binary(name) do
+    Native.get_int_property(db, name)
+  end
+  def get_int_property(%ColumnFamily{db_resource: db, cf_resource: cf}, name) when is_binary(name) do
+    Native.get_int_property_cf(db, cf, name)
+  end
+
+  @doc """
+  Returns whether writes to the database are currently stalled.
+
+  `:write_stopped` is `true` while writes are stopped altogether (for instance, because there are
+  too many level 0 files), and `:delayed_write_rate` is the rate in bytes per second writes are
+  slowed down to, or `0` if they aren't being delayed.
+
+  Together with the `no_slowdown: true` write option, which fails stalled writes with
+  `{:error, :incomplete}` rather than blocking, this lets callers shed load instead of piling up.
+
+  """
+  @spec write_stall_status(DB.t) ::
+    {:ok, %{write_stopped: boolean, delayed_write_rate: non_neg_integer}} |
+    {:error, any}
+  def write_stall_status(%DB{} = db) do
+    with {:ok, stopped} <- get_int_property(db, "rocksdb.is-write-stopped"),
+         {:ok, rate}    <- get_int_property(db, "rocksdb.actual-delayed-write-rate") do
+      {:ok, %{write_stopped: stopped == 1, delayed_write_rate: rate}}
+    else
+      :not_found -> {:error, :not_found}
+      other -> other
+    end
+  end
+
+
   @doc """
   Deletes the specified `key` from the provided database or column family.
 
@@ -328,9 +848,34 @@ defmodule Rox do
     Native.delete_cf(db, cf, key, to_map(write_opts))
   end
 
+  @doc """
+  Deletes every key in the range from `from` (inclusive) to `to` (exclusive) from the provided
+  database or column family.
+
+  Optionally takes a list of `write_opts`.
+
+  The range is deleted with a single range tombstone, which makes this a cheap operation regardless
+  of how many keys are in the range. The disk space isn't reclaimed until the range has been
+  compacted.
+
+  """
+  @spec delete_range(DB.t | ColumnFamily.t, from :: key, to :: key, write_options) :: :ok | {:error, any}
+  def delete_range(db_or_cf, from, to, write_opts \\ [])
+  def delete_range(%DB{resource: db}, from, to, write_opts) when is_binary(from) and is_binary(to) do
+    Native.delete_range(db, from, to, to_map(write_opts))
+  end
+  def delete_range(%ColumnFamily{db_resource: db, cf_resource: cf}, from, to, write_opts) when is_binary(from) and is_binary(to) do
+    Native.delete_range_cf(db, cf, from, to, to_map(write_opts))
+  end
+
   defp to_map(map) when is_map(map), do: map
   defp to_map([]), do: %{}
-  defp to_map(enum), do: Enum.into(enum, %{})
+  defp to_map(enum), do: Enum.into(enum, %{}, &nested_to_map/1)
+
+  defp nested_to_map({key, opts}) when key in @nested_options, do: {key, to_map(opts)}
+  defp nested_to_map({key, %Cache{resource: resource}}), do: {key, resource}
+  defp nested_to_map({key, %WriteBufferManager{resource: resource}}), do: {key, resource}
+  defp nested_to_map(opt), do: opt
 
 
   defp map_or_error(list, fun) do
@@ -16,6 +16,10 @@ defmodule Rox.Batch do
      | {:put_cf, {ColumnFamily.t, key :: binary, value :: binary}}
      | {:delete, key :: binary}
      | {:delete_cf, {ColumnFamily.t, key :: binary, value :: binary}}
+     | {:merge, {key :: binary, value :: binary}}
+     | {:merge_cf, {ColumnFamily.t, key :: binary, value :: binary}}
+     | {:delete_range, {from :: binary, to :: binary}}
+     | {:delete_range_cf, {ColumnFamily.t, from :: binary, to :: binary}}
 
   @doc """
   Creates a new `Batch` operation
@@ -63,15 +67,58 @@ defmodule Rox.Batch do
     %{batch | operations: [{:delete_cf, {cf, key}} | ops]}
   end
 
+  @doc """
+  Schedules the deletion of every key from `from` (inclusive) to `to` (exclusive) in the `batch`.
+
+  See `Rox.delete_range/4`.
+
+  """
+  @spec delete_range(t, from :: Rox.key, to :: Rox.key) :: t
+  def delete_range(%Batch{operations: ops} = batch, from, to) when is_binary(from) and is_binary(to) do
+    %{batch | operations: [{:delete_range, {from, to}} | ops]}
+  end
+
+  @doc """
+  Schedules the deletion of every key from `from` (inclusive) to `to` (exclusive) in
+  `column_family` in the `batch`.
+
+  """
+  @spec delete_range(t, ColumnFamily.t, from :: Rox.key, to :: Rox.key) :: t
+  def delete_range(%Batch{operations: ops} = batch, %ColumnFamily{cf_resource: cf}, from, to) when is_binary(from) and is_binary(to) do
+    %{batch | operations: [{:delete_range_cf, {cf, from, to}} | ops]}
+  end
+
+  @doc """
+  Schedules a merge operation of `value` into `key` in the `batch`.
+
+  See `Rox.merge/4`.
+
+  """
+  @spec merge(t, Rox.key, Rox.value) :: t
+  def merge(%Batch{operations: ops} = batch, key, value) when is_binary(key) do
+    %{batch | operations: [{:merge, {key, Utils.encode(value)}} | ops]}
+  end
+
+  @doc """
+  Schedules a merge operation of `value` into `key` in `column_family` in the `batch`.
+
+  """
+  @spec merge(t, ColumnFamily.t, Rox.key, Rox.value) :: t
+  def merge(%Batch{operations: ops} = batch, %ColumnFamily{cf_resource: cf}, key, value) when is_binary(key) do
+    %{batch | operations: [{:merge_cf, {cf, key, Utils.encode(value)}} | ops]}
+  end
+
   @doc """
   Atomically commits the operations in the `batch` to the `db`.
+
+  Optionally takes the same `write_options` as `Rox.put/4`, which apply to the batch as a whole.
   
   """
-  @spec write(t, DB.t) :: :ok | {:error, reason :: any}
-  def write(%Batch{operations: ops}, %DB{resource: db}) do
+  @spec write(t, DB.t, Rox.write_options) :: :ok | {:error, reason :: any}
+  def write(%Batch{operations: ops}, %DB{resource: db}, write_opts \\ []) when is_list(write_opts) do
     ops
     |> :lists.reverse
-    |> Native.batch_write(db)
+    |> Native.batch_write(db, Map.new(write_opts))
   end
 
 
@@ -0,0 +1,44 @@
+defmodule Rox.Cache do
+  @moduledoc """
+  Struct module representing a handle for a block cache.
+
+  A cache can be shared between any number of databases and column families by passing it as the
+  `:block_cache` of their `Rox.block_based_table_options`, so that they all draw from the same
+  memory budget.
+
+  """
+
+  alias Rox.Native
+
+  @typedoc "The eviction policy of a cache"
+  @type cache_type :: :lru | :clock
+
+  @typedoc "A reference to a RocksDB block cache"
+  @type t :: %__MODULE__{resource: binary, reference: reference}
+  defstruct [:resource, :reference]
+
+
+  @doc """
+  Creates a new cache of the given `type`, holding up to `capacity` bytes.
+
+  """
+  @spec new(pos_integer, cache_type) :: {:ok, t} | {:error, any}
+  def new(capacity, type \\ :lru) when is_integer(capacity) and capacity > 0 and type in [:lru, :clock] do
+    with {:ok, resource} <- Native.create_cache(type, capacity) do
+      {:ok, wrap_resource(resource)}
+    end
+  end
+
+  @doc false
+  def wrap_resource(resource) do
+    %__MODULE__{resource: resource, reference: make_ref()}
+  end
+
+  defimpl Inspect do
+    import Inspect.Algebra
+
+    def inspect(handle, opts) do
+      "#Rox.Cache<#{to_doc(handle.reference, opts)}>"
+    end
+  end
+end
@@ -50,7 +50,7 @@ defmodule Rox.ColumnFamily do
   end
 
   defimpl Enumerable do
-    def count(cf), do: {:ok, Rox.count(cf)}
+    def count(cf), do: {:ok, Rox.count_exact(cf)}
 
     def member?(cf, {key, val}) do
       with {:ok, stored_val} <- Rox.get(cf, key) do
@@ -1,11 +1,16 @@
 defmodule Rox.Cursor do
+  @default_chunk_size 100
+
   @moduledoc """
   Struct module representing a cursor for the Rox database
 
+  Rows are fetched from RocksDB in chunks of `:chunk_size` rows (defaults to
+  `#{@default_chunk_size}`), which amortizes the cost of calling into the NIF over many rows.
+
   """
 
   @type options :: [option]
-  @type option :: {:decode_values, boolean}
+  @type option :: {:decode_values, boolean} | {:chunk_size, pos_integer}
 
   @typedoc "A cursor for iterating over a database or column family"
   @type t :: %__MODULE__{
@@ -14,10 +19,29 @@ defmodule Rox.Cursor do
 
   defstruct [:resource, :mode, :options]
 
-  @type mode :: :start | :end | {:from, Rox.key, :forward | :backward}
+  @type mode
+    :: :start
+     | :end
+     | {:from, Rox.key, :forward | :reverse}
+     | {:range, from :: range_bound, to :: range_bound}
+
+  @typedoc """
+  A bound of a `{:range, from, to}` mode.
+
+  A bare key is inclusive when used as `from` and exclusive when used as `to`.
+  """
+  @type range_bound :: Rox.key | {:inclusive, Rox.key} | {:exclusive, Rox.key}
 
   @doc false
   def wrap_resource(resource, mode, options \\ []) do
+    options =
+      Keyword.put_new(options, :chunk_size, @default_chunk_size)
+
+    case options[:chunk_size] do
+      n when is_integer(n) and n > 0 -> :ok
+      other -> raise ArgumentError, ":chunk_size must be a positive integer, got: #{inspect other}"
+    end
+
     %__MODULE__{resource: resource, mode: mode, options: options}
   end
 
@@ -35,24 +59,34 @@ defmodule Rox.Cursor do
     def count(_), do: {:error, __MODULE__}
     def member?(_, _), do: {:error, __MODULE__}
 
-    def reduce(%Cursor{resource: raw, mode: mode}, {:halt, acc}, _fun) do
+    def reduce(%Cursor{} = cursor, cmd, fun) do
+      do_reduce(cursor, [], cmd, fun)
+    end
+
+    defp do_reduce(%Cursor{resource: raw, mode: mode}, _rows, {:halt, acc}, _fun) do
       Native.iterator_reset(raw, mode)
       {:halted, acc}
     end
-    def reduce(%Cursor{} = cursor, {:suspend, acc}, fun) do
-      {:suspended, acc, &reduce(cursor, &1, fun)}
+    defp do_reduce(%Cursor{} = cursor, rows, {:suspend, acc}, fun) do
+      {:suspended, acc, &do_reduce(cursor, rows, &1, fun)}
+    end
+    defp do_reduce(%Cursor{options: options} = cursor, [{key, value} | rows], {:cont, acc}, fun) do
+      value =
+        if options[:decode_values] == false, do: value, else: Utils.decode(value)
+
+      do_reduce(cursor, rows, fun.({key, value}, acc), fun)
     end
-    def reduce(%Cursor{resource: raw, mode: mode, options: options} = cursor, {:cont, acc}, fun) do
-      case Native.iterator_next(raw) do
-        :done ->
+    defp do_reduce(%Cursor{resource: raw, mode: mode, options: options} = cursor, [], {:cont, acc}, fun) do
+      case Native.iterator_next_n(raw, options[:chunk_size]) do
+        [] ->
           Native.iterator_reset(raw, mode)
           {:done, acc}
 
-        {key, value} ->
-          value =
-            if options[:decode_values] == false, do: value, else: Utils.decode(value)
+        {:error, reason} ->
+          raise ArgumentError, "could not iterate over #{inspect cursor}: #{inspect reason}"
 
-          reduce(cursor, fun.({key, value}, acc), fun)
+        rows ->
+          do_reduce(cursor, rows, {:cont, acc}, fun)
       end
     end
   end
@@ -28,7 +28,7 @@ defmodule Rox.DB do
   end
 
   defimpl Enumerable do
-    def count(db), do: {:ok, Rox.count(db)}
+    def count(db), do: {:ok, Rox.count_exact(db)}
 
     def member?(db, {key, val}) do
       with {:ok, stored_val} <- Rox.get(db, key) do
@@ -11,6 +11,14 @@ defmodule Rox.Native do
     end
   end
 
+  def close(_) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+      2 -> {:error, ""}
+    end
+  end
+
   def create_snapshot(_) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -19,6 +27,22 @@ defmodule Rox.Native do
     end
   end
 
+  def create_cache(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, ""}
+      2 -> {:error, ""}
+    end
+  end
+
+  def create_write_buffer_manager(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, ""}
+      2 -> {:error, ""}
+    end
+  end
+
   def count(_) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -33,6 +57,22 @@ defmodule Rox.Native do
     end
   end
 
+  def count_exact(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> 0
+      2 -> {:error, ""}
+    end
+  end
+
+  def count_exact_cf(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> 0
+      2 -> {:error, ""}
+    end
+  end
+
   def create_cf(_, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -41,6 +81,14 @@ defmodule Rox.Native do
     end
   end
 
+  def drop_cf(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+      2 -> {:error, ""}
+    end
+  end
+
   def cf_handle(_, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -57,6 +105,52 @@ defmodule Rox.Native do
     end
   end
 
+  def compact_range(_, _, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
+  def compact_range_cf(_, _, _, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
+  def get_property(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, ""}
+      2 -> :not_found
+    end
+  end
+
+  def get_property_cf(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, ""}
+      2 -> :not_found
+    end
+  end
+
+  def get_int_property(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, 0}
+      2 -> :not_found
+    end
+  end
+
+  def get_int_property_cf(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> {:ok, 0}
+      2 -> :not_found
+    end
+  end
+
   def put(_, _, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -71,6 +165,34 @@ defmodule Rox.Native do
     end
   end
 
+  def delete_range(_, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
+  def delete_range_cf(_, _, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
+  def merge(_, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
+  def merge_cf(_, _, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> :ok
+    end
+  end
+
   def get(_, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -99,14 +221,14 @@ defmodule Rox.Native do
     end
   end
 
-  def iterate(_, _) do
+  def iterate(_, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
       1 -> {:ok, ""}
     end
   end
 
-  def iterate_cf(_, _, _) do
+  def iterate_cf(_, _, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
       1 -> {:ok, ""}
@@ -121,6 +243,15 @@ defmodule Rox.Native do
     end
   end
 
+  def iterator_next_n(_, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> [{"", ""}]
+      2 -> []
+      3 -> {:error, ""}
+    end
+  end
+
   def iterator_reset(_, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
@@ -128,7 +259,39 @@ defmodule Rox.Native do
     end
   end
 
-  def batch_write(_, _) do
+  def multi_get(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> []
+      2 -> {:error, ""}
+    end
+  end
+
+  def multi_get_cf(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> []
+      2 -> {:error, ""}
+    end
+  end
+
+  def exists(_, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> true
+      2 -> {:error, ""}
+    end
+  end
+
+  def exists_cf(_, _, _, _) do
+    case :erlang.phash2(1, 1) do
+      0 -> raise "Nif not loaded"
+      1 -> true
+      2 -> {:error, ""}
+    end
+  end
+
+  def batch_write(_, _, _) do
     case :erlang.phash2(1, 1) do
       0 -> raise "Nif not loaded"
       1 -> :ok
@@ -39,7 +39,7 @@ defmodule Rox.Snapshot do
   end
 
   defimpl Enumerable do
-    def count(snapshot), do: {:ok, Rox.count(snapshot)}
+    def count(snapshot), do: {:ok, Rox.count_exact(snapshot)}
 
     def member?(snapshot, {key, val}) do
       with {:ok, stored_val} <- Rox.get(snapshot, key) do
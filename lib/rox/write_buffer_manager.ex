@@ -0,0 +1,61 @@
+defmodule Rox.WriteBufferManager do
+  @moduledoc """
+  Struct module representing a handle for a write buffer manager.
+
+  A write buffer manager caps the total memory used by the memtables of every database it's
+  passed to as the `:write_buffer_manager` option of `Rox.open/3`. Once the limit is reached,
+  memtables are flushed early.
+
+  """
+
+  alias Rox.{Cache, Native}
+
+  @type options :: [
+    {:cache, Cache.t} |
+    {:allow_stall, boolean}
+  ]
+
+  @typedoc "A reference to a RocksDB write buffer manager"
+  @type t :: %__MODULE__{resource: binary, reference: reference}
+  defstruct [:resource, :reference]
+
+
+  @doc """
+  Creates a new write buffer manager which limits memtables to `buffer_size` bytes in total.
+
+  ## Options
+
+    * `:cache` - charges the memory used by memtables to the given `Rox.Cache`, so that block
+      caching and memtables share a single memory budget.
+    * `:allow_stall` - stalls writes rather than exceeding `buffer_size` when flushes can't keep
+      up. Defaults to `false`.
+
+  """
+  @spec new(pos_integer, options) :: {:ok, t} | {:error, any}
+  def new(buffer_size, opts \\ []) when is_integer(buffer_size) and buffer_size > 0 and is_list(opts) do
+    allow_stall = Keyword.get(opts, :allow_stall, false)
+
+    cache =
+      case opts[:cache] do
+        %Cache{resource: resource} -> resource
+        nil -> nil
+      end
+
+    with {:ok, resource} <- Native.create_write_buffer_manager(buffer_size, allow_stall, cache) do
+      {:ok, wrap_resource(resource)}
+    end
+  end
+
+  @doc false
+  def wrap_resource(resource) do
+    %__MODULE__{resource: resource, reference: make_ref()}
+  end
+
+  defimpl Inspect do
+    import Inspect.Algebra
+
+    def inspect(handle, opts) do
+      "#Rox.WriteBufferManager<#{to_doc(handle.reference, opts)}>"
+    end
+  end
+end
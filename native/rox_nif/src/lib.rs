@@ -4,35 +4,72 @@ extern crate rustler;
 extern crate lazy_static;
 extern crate rocksdb;
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io::Write;
 use std::ops::Deref;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, LockResult, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::thread;
 
+use rustler::env::OwnedEnv;
 use rustler::resource::ResourceArc;
+use rustler::schedule::SchedulerFlags::DirtyIo;
 
 use rustler::{Decoder, Encoder, Env, Error, NifResult, Term};
 
 use rocksdb::{
-    ColumnFamily, DBCompressionType, DBIterator, Direction, IteratorMode, Options, Snapshot,
-    WriteBatch, WriteOptions, DB,
+    BlockBasedOptions, BottommostLevelCompaction, BoundColumnFamily, Cache, ColumnFamilyDescriptor,
+    CompactOptions, DBCompactionStyle, DBCompressionType, DBIterator, DBRawIterator, Direction,
+    ErrorKind, FifoCompactOptions, IteratorMode, MergeOperands, Options, ReadOptions, ReadTier,
+    SliceTransform, Snapshot, UniversalCompactOptions, WriteBatch, WriteBufferManager,
+    WriteOptions, DB,
 };
 use rustler::dynamic::TermType;
 use rustler::types::atom::Atom;
 use rustler::types::binary::{Binary, OwnedBinary};
 use rustler::types::list::ListIterator;
+use rustler::types::map::MapIterator;
+
+const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+// RocksDB's defaults for `CompressionOptions`
+const DEFAULT_WINDOW_BITS: i32 = -14;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 32767;
+
+// The number of keys `count_exact` counts between letting go of its locks
+const COUNT_CHUNK_SIZE: u64 = 10_000;
 
 mod atoms {
     rustler_atoms! {
         atom ok;
         atom error;
         atom not_found;
+        atom nil;
+        atom closed;
+        atom column_family_dropped;
+        atom invalid_option;
+        atom unknown_option;
+        atom invalid_value;
+        atom incomplete;
 
         // Batch Operation Atoms
         atom put;
         atom put_cf;
         atom delete;
         atom delete_cf;
+        atom merge;
+        atom merge_cf;
+        atom delete_range;
+        atom delete_range_cf;
+
+        // Merge Operator Atoms
+        atom int64_add;
+        atom append;
+        atom term_set_union;
+        atom max;
+        atom min;
 
         // Compression Type Atoms
         atom snappy;
@@ -40,25 +77,37 @@ mod atoms {
         atom bzip2;
         atom lz4;
         atom lz4h;
+        atom zstd;
         atom none;
 
+        // Compression Option Atoms
+        atom max_dict_bytes;
+
+        // Prefix Extractor Atoms
+        atom fixed;
+        atom capped;
+
         // Iterator Atoms
         atom forward;
         atom reverse;
         atom start;
         atom end;
         atom from;
+        atom range;
+        atom inclusive;
+        atom exclusive;
         atom done;
 
 
         // Block Based Table Option atoms
-        // atom no_block_cache;
-        // atom block_size;
-        // atom block_cache_size;
-        // atom bloom_filter_policy;
-        // atom format_version;
+        atom no_block_cache;
+        atom block_size;
+        atom block_cache_size;
+        atom bloom_filter_policy;
+        atom format_version;
         // atom skip_table_builder_flush;
-        // atom cache_index_and_filter_blocks;
+        atom cache_index_and_filter_blocks;
+        // atom block_cache; (shared with the read tiers)
 
         // CF Options Related atoms
         // atom block_cache_size_mb_for_point_lookup;
@@ -92,14 +141,18 @@ mod atoms {
         // atom inplace_update_num_locks;
         // atom table_factory_block_cache_size;
         // atom in_memory_mode;
-        // atom block_based_table_options;
 
         // DB Options
         atom total_threads;
         atom optimize_level_type_compaction_memtable_memory_budget;
         atom create_if_missing;
+        atom auto_create_column_families;
         atom max_open_files;
         atom compression_type;
+        atom compression_per_level;
+        atom compression_options;
+        atom bottommost_compression_type;
+        atom bottommost_compression_options;
         atom use_fsync;
         atom bytes_per_sync;
         atom allow_os_buffer;
@@ -115,7 +168,7 @@ mod atoms {
         atom level_zero_file_num_compaction_trigger;
         atom level_zero_slowdown_writes_trigger;
         atom level_zero_stop_writes_trigger;
-        // atom compaction_style;
+        atom compaction_style;
         atom max_background_compactions;
         atom max_background_flushes;
         atom disable_auto_compactions;
@@ -151,23 +204,72 @@ mod atoms {
         // atom skip_stats_update_on_db_open;
         // atom wal_recovery_mode;
         atom use_direct_io_for_flush_and_compaction;
+        atom prefix_extractor;
+        atom memtable_prefix_bloom_ratio;
+        atom merge_operator;
+        atom block_based_table_options;
+        atom write_buffer_manager;
+        atom universal_compaction_options;
+        atom fifo_compaction_options;
+
+        // Compaction Style Atoms
+        atom level;
+        atom universal;
+        atom fifo;
+
+        // Universal Compaction Option Atoms
+        atom size_ratio;
+        atom min_merge_width;
+        atom max_merge_width;
+        atom max_size_amplification_percent;
+
+        // FIFO Compaction Option Atoms
+        atom max_table_files_size;
+        atom ttl;
+
+        // Cache Atoms
+        atom lru;
+        atom clock;
 
         // Read Options
-        // atom fill_cache;
-        // atom iterate_upper_bound;
+        atom fill_cache;
+        atom verify_checksums;
+        atom iterate_upper_bound;
+        atom iterate_lower_bound;
+        atom read_tier;
+        atom prefix_same_as_start;
+        atom total_order_seek;
         // atom snapshot;
 
+        // Read Tier Atoms
+        atom all;
+        atom block_cache;
+        atom persisted;
+        atom memtable;
+
         // Write Options
         atom sync;
         atom disable_wal;
+        atom no_slowdown;
+        atom low_pri;
+
+        // Compaction Options
+        atom exclusive_manual_compaction;
+        atom change_level;
+        atom target_level;
+        atom bottommost_level_compaction;
+        atom skip;
+        atom if_have_compaction_filter;
+        atom force;
+        atom rox_compaction_complete;
     }
 }
 
 struct SnapshotWrapper {
-    pub snapshot: Option<Snapshot<'static>>,
+    pub snapshot: Arc<RwLock<Option<Snapshot<'static>>>>,
 
     #[allow(dead_code)]
-    db: Arc<RwLock<DB>>,
+    db: Arc<Database>,
 }
 
 unsafe impl Sync for SnapshotWrapper {}
@@ -180,21 +282,16 @@ unsafe fn shorten_snapshot<'a>(s: Snapshot<'static>) -> Snapshot<'a> {
     std::mem::transmute(s)
 }
 
-impl Deref for SnapshotWrapper {
-    type Target = Snapshot<'static>;
-
-    fn deref(&self) -> &Self::Target {
-        self.snapshot
-            .as_ref()
-            .expect("Invariant violation: Attempting to use a freed snapshot handle. Good luck.")
+fn release_snapshot(snapshot: &RwLock<Option<Snapshot<'static>>>) {
+    if let Some(snapshot) = snapshot.write().unwrap().take() {
+        unsafe { shorten_snapshot(snapshot) };
     }
 }
 
 impl Drop for SnapshotWrapper {
     fn drop(&mut self) {
-        let snapshot = std::mem::replace(&mut self.snapshot, None)
-            .expect("Invariant violation: Dropping a snapshot handle that's already been dropped.");
-        unsafe { shorten_snapshot(snapshot) };
+        // The snapshot has already been released if the database was closed
+        release_snapshot(&self.snapshot);
     }
 }
 
@@ -206,9 +303,8 @@ unsafe impl Sync for SnapshotHandle {}
 unsafe impl Send for SnapshotHandle {}
 
 impl SnapshotHandle {
-    fn new<'a>(db: &DBHandle) -> Self {
-        let read_db = db.read().unwrap();
-        let snapshot = read_db.snapshot();
+    fn new(database: &Arc<Database>, db: &DB) -> Self {
+        let snapshot = db.snapshot();
 
         // here be dragons!
         // The rocksdb snapshot type is (rightfully) parametrized to have the
@@ -216,12 +312,16 @@ impl SnapshotHandle {
         // Rust lifetimes at *all* to control the lifetime of our values - instead, we rely
         // entirely on reference counting and the Erlang garbage collector to free variables.
         // Extending this lifetime to static essentially tells Rust to let it live forever, and
-        // it'll be dropped on its own by the GC handling inside Rustler.
+        // it'll be dropped on its own by the GC handling inside Rustler (or when the database is
+        // closed, whichever comes first).
         let eternal_snapshot: Snapshot<'static> = unsafe { std::mem::transmute(snapshot) };
 
+        let snapshot = Arc::new(RwLock::new(Some(eternal_snapshot)));
+        database.register_snapshot(&snapshot);
+
         let wrapper = SnapshotWrapper {
-            snapshot: Some(eternal_snapshot),
-            db: db.deref().clone(),
+            snapshot,
+            db: database.clone(),
         };
 
         SnapshotHandle {
@@ -238,12 +338,182 @@ impl Deref for SnapshotHandle {
     }
 }
 
+///
+/// Struct holding an open database, shared between the database handle and every handle derived
+/// from it.
+///
+/// The `DB` is `None` once the database has been closed. Iterators and snapshots borrow from the
+/// `DB`, so weak references to them are kept in order to release them before the `DB` itself is
+/// dropped by `close`.
+///
+/// Column families can be dropped at runtime, so every `CFHandle` shares a flag (keyed by the
+/// column family's name) which `drop_cf` sets, keeping stale handles from being used.
+///
+/// RocksDB's `DB` is safe to read from and write to concurrently, so reads *and* writes only take
+/// the read side of the lock. The write side is reserved for operations which invalidate what
+/// readers may be using (dropping column families or closing the database).
+///
+/// Long running operations (manual compactions) hold a reference to the `DB` instead of the lock,
+/// see `Database::shared`.
+///
+struct Database {
+    db: RwLock<Option<Arc<DB>>>,
+    iterators: Mutex<Vec<Weak<RwLock<Option<DBIterator<'static>>>>>>,
+    snapshots: Mutex<Vec<Weak<RwLock<Option<Snapshot<'static>>>>>>,
+    column_families: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    shared_lock: Mutex<()>,
+    shared_released: Condvar,
+}
+
+unsafe impl Sync for Database {}
+unsafe impl Send for Database {}
+
+impl Database {
+    fn new(db: DB) -> Self {
+        Database {
+            db: RwLock::new(Some(Arc::new(db))),
+            iterators: Mutex::new(vec![]),
+            snapshots: Mutex::new(vec![]),
+            column_families: Mutex::new(HashMap::new()),
+            shared_lock: Mutex::new(()),
+            shared_released: Condvar::new(),
+        }
+    }
+
+    fn read(&self) -> LockResult<RwLockReadGuard<'_, Option<Arc<DB>>>> {
+        self.db.read()
+    }
+
+    fn write(&self) -> LockResult<RwLockWriteGuard<'_, Option<Arc<DB>>>> {
+        self.db.write()
+    }
+
+    ///
+    /// Returns a reference to the `DB` which doesn't keep the lock held, or `None` if the database
+    /// is closed. `close` cancels any background work still running through such a reference, and
+    /// waits for it to be released.
+    ///
+    fn shared(self: &Arc<Self>) -> Option<SharedDB> {
+        let db = self.db.read().unwrap().clone()?;
+
+        Some(SharedDB {
+            db: Some(db),
+            database: self.clone(),
+        })
+    }
+
+    ///
+    /// Waits for every reference handed out by `shared` to be released, given the `DB` which
+    /// `close` took out of the lock.
+    ///
+    fn wait_for_shared(&self, db: &Arc<DB>) {
+        let mut guard = self.shared_lock.lock().unwrap();
+        while Arc::strong_count(db) > 1 {
+            guard = self.shared_released.wait(guard).unwrap();
+        }
+    }
+
+    fn register_iterator(&self, iter: &Arc<RwLock<Option<DBIterator<'static>>>>) {
+        let mut iterators = self.iterators.lock().unwrap();
+        iterators.retain(|iter| iter.upgrade().is_some());
+        iterators.push(Arc::downgrade(iter));
+    }
+
+    fn register_snapshot(&self, snapshot: &Arc<RwLock<Option<Snapshot<'static>>>>) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.retain(|snapshot| snapshot.upgrade().is_some());
+        snapshots.push(Arc::downgrade(snapshot));
+    }
+
+    fn cf_dropped_flag(&self, name: &str) -> Arc<AtomicBool> {
+        self.column_families
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+            .clone()
+    }
+
+    ///
+    /// Marks every handle to the named column family as dropped. Must be called with the write
+    /// lock on the `DB` held, as handles check the flag while holding the read lock.
+    ///
+    fn mark_cf_dropped(&self, name: &str) {
+        if let Some(dropped) = self.column_families.lock().unwrap().remove(name) {
+            dropped.store(true, atomic::Ordering::SeqCst);
+        }
+    }
+
+    ///
+    /// Releases every iterator and snapshot which is still alive. Must be called with the write
+    /// lock on the `DB` held, which keeps new iterators and snapshots from being created from the
+    /// `DB` itself.
+    ///
+    fn release_dependents(&self) {
+        let snapshots: Vec<Arc<RwLock<Option<Snapshot<'static>>>>> = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter_map(|snapshot| snapshot.upgrade())
+            .collect();
+
+        // Iterators may have been created from one of the snapshots, so they need to go first.
+        // The snapshots stay locked in the meantime to keep any more iterators from being
+        // created from them.
+        let mut snapshot_guards: Vec<_> = snapshots
+            .iter()
+            .map(|snapshot| snapshot.write().unwrap())
+            .collect();
+
+        for iter in self.iterators.lock().unwrap().drain(..) {
+            if let Some(iter) = iter.upgrade() {
+                iter.write().unwrap().take();
+            }
+        }
+
+        for guard in snapshot_guards.iter_mut() {
+            if let Some(snapshot) = guard.take() {
+                unsafe { shorten_snapshot(snapshot) };
+            }
+        }
+    }
+}
+
+///
+/// A reference to the `DB` handed out by `Database::shared`, which wakes up `close` once it's
+/// released.
+///
+struct SharedDB {
+    db: Option<Arc<DB>>,
+    database: Arc<Database>,
+}
+
+impl Deref for SharedDB {
+    type Target = DB;
+
+    fn deref(&self) -> &Self::Target {
+        self.db.as_ref().unwrap()
+    }
+}
+
+impl Drop for SharedDB {
+    fn drop(&mut self) {
+        // `close` counts the references to the `DB` with the lock held, so this one has to be gone
+        // before it's woken up
+        self.db.take();
+
+        let _guard = self.database.shared_lock.lock().unwrap();
+        self.database.shared_released.notify_all();
+    }
+}
+
 struct DBHandle {
-    pub db: Arc<RwLock<DB>>,
+    pub db: Arc<Database>,
 }
 
 impl Deref for DBHandle {
-    type Target = Arc<RwLock<DB>>;
+    type Target = Arc<Database>;
 
     fn deref(&self) -> &Self::Target {
         &self.db
@@ -257,24 +527,34 @@ impl Deref for DBHandle {
 /// from being dropped before the dependent child objects are
 ///
 enum DatabaseRef {
-    DB(Arc<RwLock<DB>>),
+    DB(Arc<Database>),
     Snapshot(Arc<SnapshotWrapper>),
 }
 
-impl<'a> From<&'a Arc<RwLock<DB>>> for DatabaseRef {
-    fn from(db: &Arc<RwLock<DB>>) -> Self {
+impl From<&Arc<Database>> for DatabaseRef {
+    fn from(db: &Arc<Database>) -> Self {
         DatabaseRef::DB(db.clone())
     }
 }
 
-impl<'a> From<&'a Arc<SnapshotWrapper>> for DatabaseRef {
+impl From<&Arc<SnapshotWrapper>> for DatabaseRef {
     fn from(snapshot: &Arc<SnapshotWrapper>) -> Self {
         DatabaseRef::Snapshot(snapshot.clone())
     }
 }
 
+impl DatabaseRef {
+    fn database(&self) -> &Arc<Database> {
+        match *self {
+            DatabaseRef::DB(ref db) => db,
+            DatabaseRef::Snapshot(ref snapshot) => &snapshot.db,
+        }
+    }
+}
+
 struct CFHandle {
-    pub cf: ColumnFamily,
+    pub name: String,
+    dropped: Arc<AtomicBool>,
     #[allow(dead_code)]
     db: DatabaseRef,
 }
@@ -282,8 +562,32 @@ struct CFHandle {
 unsafe impl Sync for CFHandle {}
 unsafe impl Send for CFHandle {}
 
+impl CFHandle {
+    fn new(name: &str, db: DatabaseRef) -> Self {
+        let dropped = db.database().cf_dropped_flag(name);
+
+        CFHandle {
+            name: name.to_string(),
+            dropped,
+            db,
+        }
+    }
+
+    ///
+    /// Looks the column family up in `db`, or returns `None` if it has since been dropped. The
+    /// read lock on the `DB` must be held for as long as the column family is in use.
+    ///
+    fn get<'d>(&self, db: &'d DB) -> Option<Arc<BoundColumnFamily<'d>>> {
+        if self.dropped.load(atomic::Ordering::SeqCst) {
+            None
+        } else {
+            db.cf_handle(&self.name)
+        }
+    }
+}
+
 struct IteratorHandle {
-    pub iter: RwLock<DBIterator>,
+    pub iter: Arc<RwLock<Option<DBIterator<'static>>>>,
     #[allow(dead_code)]
     db: DatabaseRef,
 }
@@ -291,6 +595,34 @@ struct IteratorHandle {
 unsafe impl Sync for IteratorHandle {}
 unsafe impl Send for IteratorHandle {}
 
+impl IteratorHandle {
+    fn new(iter: DBIterator, db: DatabaseRef) -> Self {
+        // Iterators borrow from their `DB` just like snapshots do (see `SnapshotHandle::new`), and
+        // are likewise kept alive by reference counting instead. `close` releases any iterators
+        // which are still alive before dropping the `DB`.
+        let eternal_iter: DBIterator<'static> = unsafe { std::mem::transmute(iter) };
+
+        let iter = Arc::new(RwLock::new(Some(eternal_iter)));
+        db.database().register_iterator(&iter);
+
+        IteratorHandle { iter, db }
+    }
+}
+
+struct CacheHandle {
+    pub cache: Cache,
+}
+
+unsafe impl Sync for CacheHandle {}
+unsafe impl Send for CacheHandle {}
+
+struct WriteBufferManagerHandle {
+    pub manager: WriteBufferManager,
+}
+
+unsafe impl Sync for WriteBufferManagerHandle {}
+unsafe impl Send for WriteBufferManagerHandle {}
+
 struct CompressionType {
     pub raw: DBCompressionType,
 }
@@ -320,23 +652,275 @@ impl<'a> Decoder<'a> for CompressionType {
             Ok(CompressionType {
                 raw: DBCompressionType::Lz4hc,
             })
+        } else if atoms::zstd() == term {
+            Ok(CompressionType {
+                raw: DBCompressionType::Zstd,
+            })
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+impl From<CompressionType> for DBCompressionType {
+    fn from(val: CompressionType) -> Self {
+        val.raw
+    }
+}
+
+struct ReadTierType {
+    pub raw: ReadTier,
+}
+impl<'a> Decoder<'a> for ReadTierType {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::all() == term {
+            Ok(ReadTierType { raw: ReadTier::All })
+        } else if atoms::block_cache() == term {
+            Ok(ReadTierType {
+                raw: ReadTier::BlockCache,
+            })
+        } else if atoms::persisted() == term {
+            Ok(ReadTierType {
+                raw: ReadTier::Persisted,
+            })
+        } else if atoms::memtable() == term {
+            Ok(ReadTierType {
+                raw: ReadTier::Memtable,
+            })
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+impl From<ReadTierType> for ReadTier {
+    fn from(val: ReadTierType) -> Self {
+        val.raw
+    }
+}
+
+enum PrefixExtractor {
+    Fixed(usize),
+    Capped(usize),
+}
+impl<'a> Decoder<'a> for PrefixExtractor {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let (kind, len): (Atom, usize) = term.decode()?;
+
+        if kind == atoms::fixed() {
+            Ok(PrefixExtractor::Fixed(len))
+        } else if kind == atoms::capped() {
+            Ok(PrefixExtractor::Capped(len))
         } else {
             Err(Error::BadArg)
         }
     }
 }
 
-impl Into<DBCompressionType> for CompressionType {
-    fn into(self) -> DBCompressionType {
-        self.raw
+impl PrefixExtractor {
+    fn apply(self, opts: &mut Options, deferred: &mut Vec<(&'static str, String)>) {
+        match self {
+            PrefixExtractor::Fixed(len) => {
+                opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(len))
+            }
+            // rust-rocksdb has no constructor for RocksDB's capped prefix extractor, which can
+            // only be selected by name
+            PrefixExtractor::Capped(len) => {
+                deferred.push(("prefix_extractor", format!("rocksdb.CappedPrefix.{}", len)))
+            }
+        }
+    }
+}
+
+// Prefix used by `Rox.Utils.encode/1` to mark values which were serialized with
+// `:erlang.term_to_binary/1`. Binaries are stored as-is.
+const ENCODED_TERM_PREFIX: &[u8] = b"_$rx:";
+
+fn decode_stored_term<'a>(env: Env<'a>, bytes: &[u8]) -> Option<Term<'a>> {
+    if bytes.starts_with(ENCODED_TERM_PREFIX) {
+        env.binary_to_term(&bytes[ENCODED_TERM_PREFIX.len()..])
+            .map(|(term, _)| term)
+    } else {
+        Some(encode_binary(env, bytes))
+    }
+}
+
+fn encode_stored_term(term: Term) -> Vec<u8> {
+    match term.decode::<Binary>() {
+        Ok(bin) => bin.as_slice().to_vec(),
+        Err(_) => {
+            let mut encoded = ENCODED_TERM_PREFIX.to_vec();
+            encoded.extend_from_slice(term.to_binary().as_slice());
+            encoded
+        }
+    }
+}
+
+///
+/// The merge operators which can be selected through the `merge_operator` option.
+///
+/// All of them are associative, so RocksDB is free to combine operands ahead of time during
+/// compactions. The merge functions run on RocksDB's own threads, and return `None` (which
+/// RocksDB reports as a corruption error) if an operand can't be decoded.
+///
+enum MergeOperatorType {
+    Int64Add,
+    Append(Vec<u8>),
+    TermSetUnion,
+    Max,
+    Min,
+}
+
+impl<'a> Decoder<'a> for MergeOperatorType {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if atoms::int64_add() == term {
+            Ok(MergeOperatorType::Int64Add)
+        } else if atoms::append() == term {
+            Ok(MergeOperatorType::Append(vec![]))
+        } else if atoms::term_set_union() == term {
+            Ok(MergeOperatorType::TermSetUnion)
+        } else if atoms::max() == term {
+            Ok(MergeOperatorType::Max)
+        } else if atoms::min() == term {
+            Ok(MergeOperatorType::Min)
+        } else {
+            let (kind, delimiter): (Atom, Binary) = term.decode()?;
+
+            if kind == atoms::append() {
+                Ok(MergeOperatorType::Append(delimiter.as_slice().to_vec()))
+            } else {
+                Err(Error::BadArg)
+            }
+        }
+    }
+}
+
+impl MergeOperatorType {
+    fn apply(self, opts: &mut Options) {
+        match self {
+            MergeOperatorType::Int64Add => {
+                opts.set_merge_operator_associative("rox.int64_add", merge_int64_add)
+            }
+            MergeOperatorType::Append(delimiter) => opts.set_merge_operator_associative(
+                "rox.append",
+                move |_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands| {
+                    merge_append(&delimiter, existing, operands)
+                },
+            ),
+            MergeOperatorType::TermSetUnion => {
+                opts.set_merge_operator_associative("rox.term_set_union", merge_term_set_union)
+            }
+            MergeOperatorType::Max => opts.set_merge_operator_associative("rox.max", merge_max),
+            MergeOperatorType::Min => opts.set_merge_operator_associative("rox.min", merge_min),
+        }
+    }
+}
+
+fn merge_int64_add(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    OwnedEnv::new().run(|env| {
+        let mut sum: i64 = match existing {
+            Some(bytes) => decode_stored_term(env, bytes)?.decode().ok()?,
+            None => 0,
+        };
+
+        for operand in operands {
+            let n: i64 = decode_stored_term(env, operand)?.decode().ok()?;
+            sum = sum.wrapping_add(n);
+        }
+
+        Some(encode_stored_term(sum.encode(env)))
+    })
+}
+
+fn merge_append(
+    delimiter: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut result: Option<Vec<u8>> = existing.map(|bytes| bytes.to_vec());
+
+    for operand in operands {
+        if let Some(ref mut acc) = result {
+            acc.extend_from_slice(delimiter);
+            acc.extend_from_slice(operand);
+            continue;
+        }
+
+        result = Some(operand.to_vec());
     }
+
+    Some(result.unwrap_or_default())
+}
+
+fn merge_term_set_union(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    OwnedEnv::new().run(|env| {
+        let mut terms: Vec<Term> = match existing {
+            Some(bytes) => decode_stored_term(env, bytes)?.decode().ok()?,
+            None => vec![],
+        };
+
+        for operand in operands {
+            let list: Vec<Term> = decode_stored_term(env, operand)?.decode().ok()?;
+            terms.extend(list);
+        }
+
+        // Sorted by the Erlang term order, the same as an `:ordsets` set
+        terms.sort();
+        terms.dedup();
+
+        Some(encode_stored_term(terms.encode(env)))
+    })
+}
+
+fn merge_extreme(
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+    keep: Ordering,
+) -> Option<Vec<u8>> {
+    OwnedEnv::new().run(|env| {
+        let mut best: Option<Term> = match existing {
+            Some(bytes) => Some(decode_stored_term(env, bytes)?),
+            None => None,
+        };
+
+        for operand in operands {
+            let term = decode_stored_term(env, operand)?;
+
+            best = match best {
+                Some(current) if current.cmp(&term) != keep.reverse() => Some(current),
+                _ => Some(term),
+            };
+        }
+
+        best.map(encode_stored_term)
+    })
+}
+
+fn merge_max(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    merge_extreme(existing, operands, Ordering::Greater)
+}
+
+fn merge_min(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    merge_extreme(existing, operands, Ordering::Less)
 }
 
 enum BatchOperation<'a> {
     Put(&'a [u8], &'a [u8]),
-    PutCf(ColumnFamily, &'a [u8], &'a [u8]),
+    PutCf(ResourceArc<CFHandle>, &'a [u8], &'a [u8]),
     Delete(&'a [u8]),
-    DeleteCf(ColumnFamily, &'a [u8]),
+    DeleteCf(ResourceArc<CFHandle>, &'a [u8]),
+    Merge(&'a [u8], &'a [u8]),
+    MergeCf(ResourceArc<CFHandle>, &'a [u8], &'a [u8]),
+    DeleteRange(&'a [u8], &'a [u8]),
+    DeleteRangeCf(ResourceArc<CFHandle>, &'a [u8], &'a [u8]),
 }
 
 impl<'a> Decoder<'a> for BatchOperation<'a> {
@@ -347,13 +931,25 @@ impl<'a> Decoder<'a> for BatchOperation<'a> {
             Ok(BatchOperation::Put(key.as_slice(), val.as_slice()))
         } else if atoms::put_cf() == operation {
             let (cf, key, val): (ResourceArc<CFHandle>, Binary, Binary) = details.decode()?;
-            Ok(BatchOperation::PutCf(cf.cf, key.as_slice(), val.as_slice()))
+            Ok(BatchOperation::PutCf(cf, key.as_slice(), val.as_slice()))
         } else if atoms::delete() == operation {
             let key: Binary = details.decode()?;
             Ok(BatchOperation::Delete(key.as_slice()))
         } else if atoms::delete_cf() == operation {
             let (cf, key): (ResourceArc<CFHandle>, Binary) = details.decode()?;
-            Ok(BatchOperation::DeleteCf(cf.cf, key.as_slice()))
+            Ok(BatchOperation::DeleteCf(cf, key.as_slice()))
+        } else if atoms::merge() == operation {
+            let (key, val): (Binary, Binary) = details.decode()?;
+            Ok(BatchOperation::Merge(key.as_slice(), val.as_slice()))
+        } else if atoms::merge_cf() == operation {
+            let (cf, key, val): (ResourceArc<CFHandle>, Binary, Binary) = details.decode()?;
+            Ok(BatchOperation::MergeCf(cf, key.as_slice(), val.as_slice()))
+        } else if atoms::delete_range() == operation {
+            let (from, to): (Binary, Binary) = details.decode()?;
+            Ok(BatchOperation::DeleteRange(from.as_slice(), to.as_slice()))
+        } else if atoms::delete_range_cf() == operation {
+            let (cf, from, to): (ResourceArc<CFHandle>, Binary, Binary) = details.decode()?;
+            Ok(BatchOperation::DeleteRangeCf(cf, from.as_slice(), to.as_slice()))
         } else {
             Err(Error::BadArg)
         }
@@ -369,156 +965,591 @@ macro_rules! handle_error {
     };
 }
 
-fn decode_write_options<'a>(env: Env<'a>, arg: Term<'a>) -> NifResult<WriteOptions> {
-    let mut opts = WriteOptions::new();
+///
+/// Options decoded from an options map. A few options (capped prefix extractors and the FIFO TTL)
+/// have no setter on rust-rocksdb's `Options`, so they are kept as option strings instead, which
+/// `set_deferred_options` applies once the column family has been opened.
+///
+#[derive(Clone, Default)]
+struct DecodedOptions {
+    opts: Options,
+    deferred: Vec<(&'static str, String)>,
+}
 
-    if let Ok(sync) = arg.map_get(atoms::sync().to_term(env)) {
-        opts.set_sync(sync.decode()?);
+fn set_deferred_options(
+    db: &DB,
+    cf: Option<&Arc<BoundColumnFamily>>,
+    deferred: &[(&'static str, String)],
+) -> Result<(), rocksdb::Error> {
+    if deferred.is_empty() {
+        return Ok(());
     }
 
-    if let Ok(disable_wal) = arg.map_get(atoms::disable_wal().to_term(env)) {
-        opts.disable_wal(disable_wal.decode()?);
+    let deferred: Vec<(&str, &str)> = deferred
+        .iter()
+        .map(|&(key, ref value)| (key, value.as_str()))
+        .collect();
+
+    match cf {
+        Some(cf) => db.set_options_cf(cf, &deferred),
+        None => db.set_options(&deferred),
     }
+}
 
-    Ok(opts)
+macro_rules! handle_closed {
+    ($env:expr, $e:expr) => {
+        match $e {
+            Some(inner) => inner,
+            None => return Ok((atoms::error(), atoms::closed()).encode($env)),
+        }
+    };
 }
 
-// fn decode_read_options<'a>(env: Env<'a>, arg: Term<'a>) -> NifResult<ReadOptions> {
-//     let mut opts = ReadOptions::default();
+macro_rules! handle_dropped {
+    ($env:expr, $e:expr) => {
+        match $e {
+            Some(inner) => inner,
+            None => return Ok((atoms::error(), atoms::column_family_dropped()).encode($env)),
+        }
+    };
+}
 
-//     if let Ok(fill_cache) = arg.map_get(atoms::fill_cache().to_term(env)) {
-//         opts.fill_cache(fill_cache.decode()?);
-//     }
+///
+/// Error returned by the decoders of options maps, naming the offending key. It's encoded as
+/// `{:error, {:invalid_option, key, reason}}`.
+///
+struct OptionError<'a> {
+    key: Term<'a>,
+    reason: Atom,
+}
 
-//     // TODO implement handling snapshots
-//     if let Ok(iter_upper) = arg.map_get(atoms::iterate_upper_bound().to_term(env)) {
-//         let key: &str = iter_upper.decode()?;
-//         opts.set_iterate_upper_bound(key);
-//     }
+impl<'a> Encoder for OptionError<'a> {
+    fn encode<'b>(&self, env: Env<'b>) -> Term<'b> {
+        let reason = (atoms::invalid_option(), self.key, self.reason);
+        (atoms::error(), reason).encode(env)
+    }
+}
 
-//     Ok(opts)
-// }
+type OptionResult<'a, T> = Result<T, OptionError<'a>>;
 
-fn decode_db_options<'a>(env: Env<'a>, arg: Term<'a>) -> NifResult<Options> {
-    let mut opts = Options::default();
+///
+/// An options map which keeps track of the keys that have been looked up in it, so that any
+/// unknown keys can be rejected by `finish` once decoding is done.
+///
+struct OptionMap<'a> {
+    env: Env<'a>,
+    map: Term<'a>,
+    known: Vec<Atom>,
+}
 
-    if let Ok(count) = arg.map_get(atoms::total_threads().to_term(env)) {
-        opts.increase_parallelism(count.decode()?);
+impl<'a> OptionMap<'a> {
+    fn new(env: Env<'a>, map: Term<'a>) -> Self {
+        OptionMap {
+            env,
+            map,
+            known: vec![],
+        }
     }
 
-    if let Ok(memtable_budget) =
-        arg.map_get(atoms::optimize_level_type_compaction_memtable_memory_budget().to_term(env))
-    {
-        let i_size: u64 = memtable_budget.decode()?;
-        opts.optimize_level_style_compaction(i_size as usize);
-    }
+    fn get(&mut self, key: Atom) -> Option<OptionValue<'a>> {
+        self.known.push(key);
 
-    if let Ok(create_if_missing) = arg.map_get(atoms::create_if_missing().to_term(env)) {
-        opts.create_if_missing(create_if_missing.decode()?);
+        let key = key.to_term(self.env);
+        match self.map.map_get(key) {
+            Ok(value) => Some(OptionValue {
+                env: self.env,
+                key,
+                value,
+            }),
+            Err(_) => None,
+        }
     }
 
-    if let Ok(compression_type_opt) = arg.map_get(atoms::compression_type().to_term(env)) {
-        let compression_type: CompressionType = compression_type_opt.decode()?;
-        opts.set_compression_type(compression_type.into());
+    fn finish(self) -> OptionResult<'a, ()> {
+        if let Some(iter) = MapIterator::new(self.map) {
+            for (key, _) in iter {
+                if !self.known.iter().any(|known| *known == key) {
+                    return Err(OptionError {
+                        key,
+                        reason: atoms::unknown_option(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
     }
+}
 
-    // TODO: Set Compression Type Per Level
+#[derive(Clone, Copy)]
+struct OptionValue<'a> {
+    env: Env<'a>,
+    key: Term<'a>,
+    value: Term<'a>,
+}
 
-    if let Ok(max_open_files) = arg.map_get(atoms::max_open_files().to_term(env)) {
-        opts.set_max_open_files(max_open_files.decode()?);
+impl<'a> OptionValue<'a> {
+    fn decode<T: Decoder<'a>>(self) -> OptionResult<'a, T> {
+        self.value.decode().map_err(|_| self.invalid())
     }
 
-    if let Ok(use_fsync) = arg.map_get(atoms::use_fsync().to_term(env)) {
-        opts.set_use_fsync(use_fsync.decode()?);
+    ///
+    /// Returns the value as a nested options map.
+    ///
+    fn options(self) -> OptionResult<'a, OptionMap<'a>> {
+        match self.value.get_type() {
+            TermType::Map => Ok(OptionMap::new(self.env, self.value)),
+            _ => Err(self.invalid()),
+        }
     }
 
-    if let Ok(bytes_per_sync) = arg.map_get(atoms::bytes_per_sync().to_term(env)) {
-        opts.set_bytes_per_sync(bytes_per_sync.decode()?);
+    fn invalid(self) -> OptionError<'a> {
+        OptionError {
+            key: self.key,
+            reason: atoms::invalid_value(),
+        }
     }
+}
 
-    if let Ok(allow_os_buffer) = arg.map_get(atoms::allow_os_buffer().to_term(env)) {
-        #[allow(deprecated)]
-        opts.set_allow_os_buffer(allow_os_buffer.decode()?);
+macro_rules! handle_option_error {
+    ($env:expr, $e:expr) => {
+        match $e {
+            Ok(inner) => inner,
+            Err(err) => return Ok(err.encode($env)),
+        }
+    };
+}
+
+fn decode_write_options<'a>(env: Env<'a>, arg: Term<'a>) -> OptionResult<'a, WriteOptions> {
+    let mut arg = OptionMap::new(env, arg);
+    let mut opts = WriteOptions::new();
+
+    if let Some(sync) = arg.get(atoms::sync()) {
+        opts.set_sync(sync.decode()?);
     }
 
-    if let Ok(nbits) = arg.map_get(atoms::table_cache_num_shard_bits().to_term(env)) {
-        opts.set_table_cache_num_shard_bits(nbits.decode()?);
+    if let Some(disable_wal) = arg.get(atoms::disable_wal()) {
+        opts.disable_wal(disable_wal.decode()?);
     }
 
-    if let Ok(nbuf) = arg.map_get(atoms::min_write_buffer_number().to_term(env)) {
-        opts.set_min_write_buffer_number(nbuf.decode()?);
+    // Fails writes with `Incomplete` rather than waiting when they would be stalled
+    if let Some(no_slowdown) = arg.get(atoms::no_slowdown()) {
+        opts.set_no_slowdown(no_slowdown.decode()?);
     }
 
-    if let Ok(nbuf) = arg.map_get(atoms::max_write_buffer_number().to_term(env)) {
-        opts.set_max_write_buffer_number(nbuf.decode()?);
+    if let Some(low_pri) = arg.get(atoms::low_pri()) {
+        opts.set_low_pri(low_pri.decode()?);
     }
 
-    if let Ok(size) = arg.map_get(atoms::write_buffer_size().to_term(env)) {
-        let i_size: u64 = size.decode()?;
-        opts.set_write_buffer_size(i_size as usize);
+    arg.finish()?;
+    Ok(opts)
+}
+
+fn decode_read_options<'a>(env: Env<'a>, arg: Term<'a>) -> OptionResult<'a, ReadOptions> {
+    let mut arg = OptionMap::new(env, arg);
+    let mut opts = ReadOptions::default();
+
+    if let Some(fill_cache) = arg.get(atoms::fill_cache()) {
+        opts.fill_cache(fill_cache.decode()?);
     }
 
-    if let Ok(max_bytes) = arg.map_get(atoms::max_bytes_for_level_base().to_term(env)) {
-        opts.set_max_bytes_for_level_base(max_bytes.decode()?);
+    if let Some(verify) = arg.get(atoms::verify_checksums()) {
+        opts.set_verify_checksums(verify.decode()?);
     }
 
-    if let Ok(multiplier) = arg.map_get(atoms::max_bytes_for_level_multiplier().to_term(env)) {
-        opts.set_max_bytes_for_level_multiplier(multiplier.decode()?);
+    // The bounds are copied into the options, since iterators outlive the binaries they were
+    // decoded from
+    if let Some(upper) = arg.get(atoms::iterate_upper_bound()) {
+        let key: Binary = upper.decode()?;
+        opts.set_iterate_upper_bound(key.as_slice().to_vec());
     }
 
-    if let Ok(max_size) = arg.map_get(atoms::max_manifest_file_size().to_term(env)) {
+    if let Some(lower) = arg.get(atoms::iterate_lower_bound()) {
+        let key: Binary = lower.decode()?;
+        opts.set_iterate_lower_bound(key.as_slice().to_vec());
+    }
+
+    if let Some(tier) = arg.get(atoms::read_tier()) {
+        let read_tier: ReadTierType = tier.decode()?;
+        opts.set_read_tier(read_tier.into());
+    }
+
+    if let Some(same_prefix) = arg.get(atoms::prefix_same_as_start()) {
+        opts.set_prefix_same_as_start(same_prefix.decode()?);
+    }
+
+    if let Some(total_order) = arg.get(atoms::total_order_seek()) {
+        opts.set_total_order_seek(total_order.decode()?);
+    }
+
+    arg.finish()?;
+    Ok(opts)
+}
+
+fn decode_compact_options<'a>(env: Env<'a>, arg: Term<'a>) -> OptionResult<'a, CompactOptions> {
+    let mut arg = OptionMap::new(env, arg);
+    let mut opts = CompactOptions::default();
+
+    if let Some(exclusive) = arg.get(atoms::exclusive_manual_compaction()) {
+        opts.set_exclusive_manual_compaction(exclusive.decode()?);
+    }
+
+    if let Some(change_level) = arg.get(atoms::change_level()) {
+        opts.set_change_level(change_level.decode()?);
+    }
+
+    if let Some(level) = arg.get(atoms::target_level()) {
+        opts.set_target_level(level.decode()?);
+    }
+
+    if let Some(bottommost_opt) = arg.get(atoms::bottommost_level_compaction()) {
+        let bottommost: Atom = bottommost_opt.decode()?;
+
+        if bottommost == atoms::skip() {
+            opts.set_bottommost_level_compaction(BottommostLevelCompaction::Skip);
+        } else if bottommost == atoms::if_have_compaction_filter() {
+            opts.set_bottommost_level_compaction(BottommostLevelCompaction::IfHaveCompactionFilter);
+        } else if bottommost == atoms::force() {
+            opts.set_bottommost_level_compaction(BottommostLevelCompaction::Force);
+        } else {
+            return Err(bottommost_opt.invalid());
+        }
+    }
+
+    arg.finish()?;
+    Ok(opts)
+}
+
+fn decode_db_options<'a>(env: Env<'a>, arg: Term<'a>) -> OptionResult<'a, DecodedOptions> {
+    let mut arg = OptionMap::new(env, arg);
+    let mut opts = Options::default();
+
+    if let Some(count) = arg.get(atoms::total_threads()) {
+        opts.increase_parallelism(count.decode()?);
+    }
+
+    if let Some(create_if_missing) = arg.get(atoms::create_if_missing()) {
+        opts.create_if_missing(create_if_missing.decode()?);
+    }
+
+    if let Some(auto_create) = arg.get(atoms::auto_create_column_families()) {
+        opts.create_missing_column_families(auto_create.decode()?);
+    }
+
+    if let Some(max_open_files) = arg.get(atoms::max_open_files()) {
+        opts.set_max_open_files(max_open_files.decode()?);
+    }
+
+    if let Some(use_fsync) = arg.get(atoms::use_fsync()) {
+        opts.set_use_fsync(use_fsync.decode()?);
+    }
+
+    if let Some(bytes_per_sync) = arg.get(atoms::bytes_per_sync()) {
+        opts.set_bytes_per_sync(bytes_per_sync.decode()?);
+    }
+
+    if let Some(allow_os_buffer) = arg.get(atoms::allow_os_buffer()) {
+        #[allow(deprecated)]
+        opts.set_allow_os_buffer(allow_os_buffer.decode()?);
+    }
+
+    if let Some(nbits) = arg.get(atoms::table_cache_num_shard_bits()) {
+        opts.set_table_cache_num_shard_bits(nbits.decode()?);
+    }
+
+    if let Some(max_size) = arg.get(atoms::max_manifest_file_size()) {
         let i_size: u64 = max_size.decode()?;
         opts.set_max_manifest_file_size(i_size as usize);
     }
 
-    if let Ok(target_size) = arg.map_get(atoms::target_file_size_base().to_term(env)) {
+    if let Some(n) = arg.get(atoms::max_background_compactions()) {
+        #[allow(deprecated)]
+        opts.set_max_background_compactions(n.decode()?);
+    }
+
+    if let Some(n) = arg.get(atoms::max_background_flushes()) {
+        #[allow(deprecated)]
+        opts.set_max_background_flushes(n.decode()?);
+    }
+
+    // Todo: set WAL Recovery Mode
+
+    if let Some(enabled) = arg.get(atoms::use_direct_io_for_flush_and_compaction()) {
+        opts.set_use_direct_io_for_flush_and_compaction(enabled.decode()?);
+    }
+
+    if let Some(manager) = arg.get(atoms::write_buffer_manager()) {
+        let manager_arc: ResourceArc<WriteBufferManagerHandle> = manager.decode()?;
+        opts.set_write_buffer_manager(&manager_arc.manager);
+    }
+
+    // The options of a database double as the options of its column families
+    let deferred = decode_cf_options_into(&mut arg, &mut opts)?;
+
+    arg.finish()?;
+    Ok(DecodedOptions { opts, deferred })
+}
+
+///
+/// Decodes the options which can be set per column family from `arg` into `opts`, returning the
+/// ones which have to be deferred (see `DecodedOptions`).
+///
+fn decode_cf_options_into<'a>(
+    arg: &mut OptionMap<'a>,
+    opts: &mut Options,
+) -> OptionResult<'a, Vec<(&'static str, String)>> {
+    let mut deferred = vec![];
+
+    if let Some(memtable_budget) =
+        arg.get(atoms::optimize_level_type_compaction_memtable_memory_budget())
+    {
+        let i_size: u64 = memtable_budget.decode()?;
+        opts.optimize_level_style_compaction(i_size as usize);
+    }
+
+    if let Some(compression_type_opt) = arg.get(atoms::compression_type()) {
+        let compression_type: CompressionType = compression_type_opt.decode()?;
+        opts.set_compression_type(compression_type.into());
+    }
+
+    if let Some(per_level) = arg.get(atoms::compression_per_level()) {
+        let compression_types: Vec<CompressionType> = per_level.decode()?;
+        let compression_types: Vec<DBCompressionType> =
+            compression_types.into_iter().map(|t| t.into()).collect();
+        opts.set_compression_per_level(&compression_types);
+    }
+
+    if let Some(compression_opts) = arg.get(atoms::compression_options()) {
+        let (level, max_dict_bytes) = decode_compression_options(compression_opts.options()?)?;
+        opts.set_compression_options(DEFAULT_WINDOW_BITS, level, 0, max_dict_bytes);
+    }
+
+    if let Some(compression_type_opt) = arg.get(atoms::bottommost_compression_type()) {
+        let compression_type: CompressionType = compression_type_opt.decode()?;
+        opts.set_bottommost_compression_type(compression_type.into());
+    }
+
+    if let Some(compression_opts) = arg.get(atoms::bottommost_compression_options()) {
+        let (level, max_dict_bytes) = decode_compression_options(compression_opts.options()?)?;
+        opts.set_bottommost_compression_options(
+            DEFAULT_WINDOW_BITS,
+            level,
+            0,
+            max_dict_bytes,
+            true,
+        );
+    }
+
+    if let Some(nbuf) = arg.get(atoms::min_write_buffer_number()) {
+        opts.set_min_write_buffer_number(nbuf.decode()?);
+    }
+
+    if let Some(nbuf) = arg.get(atoms::max_write_buffer_number()) {
+        opts.set_max_write_buffer_number(nbuf.decode()?);
+    }
+
+    if let Some(size) = arg.get(atoms::write_buffer_size()) {
+        let i_size: u64 = size.decode()?;
+        opts.set_write_buffer_size(i_size as usize);
+    }
+
+    if let Some(max_bytes) = arg.get(atoms::max_bytes_for_level_base()) {
+        opts.set_max_bytes_for_level_base(max_bytes.decode()?);
+    }
+
+    if let Some(multiplier) = arg.get(atoms::max_bytes_for_level_multiplier()) {
+        opts.set_max_bytes_for_level_multiplier(multiplier.decode()?);
+    }
+
+    if let Some(target_size) = arg.get(atoms::target_file_size_base()) {
         opts.set_target_file_size_base(target_size.decode()?);
     }
 
-    if let Ok(to_merge) = arg.map_get(atoms::min_write_buffer_number_to_merge().to_term(env)) {
+    if let Some(to_merge) = arg.get(atoms::min_write_buffer_number_to_merge()) {
         opts.set_min_write_buffer_number_to_merge(to_merge.decode()?);
     }
 
-    if let Ok(n) = arg.map_get(atoms::level_zero_file_num_compaction_trigger().to_term(env)) {
+    if let Some(n) = arg.get(atoms::level_zero_file_num_compaction_trigger()) {
         opts.set_level_zero_file_num_compaction_trigger(n.decode()?);
     }
 
-    if let Ok(n) = arg.map_get(atoms::level_zero_slowdown_writes_trigger().to_term(env)) {
+    if let Some(n) = arg.get(atoms::level_zero_slowdown_writes_trigger()) {
         opts.set_level_zero_slowdown_writes_trigger(n.decode()?);
     }
 
-    if let Ok(n) = arg.map_get(atoms::level_zero_stop_writes_trigger().to_term(env)) {
+    if let Some(n) = arg.get(atoms::level_zero_stop_writes_trigger()) {
         opts.set_level_zero_stop_writes_trigger(n.decode()?);
     }
 
-    // Todo set compaction style
+    let mut manual_compactions_only = false;
+
+    if let Some(style_opt) = arg.get(atoms::compaction_style()) {
+        let style: Atom = style_opt.decode()?;
+
+        if style == atoms::level() {
+            opts.set_compaction_style(DBCompactionStyle::Level);
+        } else if style == atoms::universal() {
+            opts.set_compaction_style(DBCompactionStyle::Universal);
+        } else if style == atoms::fifo() {
+            opts.set_compaction_style(DBCompactionStyle::Fifo);
+        } else if style == atoms::none() {
+            // rust-rocksdb doesn't expose kCompactionStyleNone, which only allows manual
+            // compactions, so the same is done by turning off automatic compactions
+            opts.set_disable_auto_compactions(true);
+            manual_compactions_only = true;
+        } else {
+            return Err(style_opt.invalid());
+        }
+    }
 
-    if let Ok(n) = arg.map_get(atoms::max_background_compactions().to_term(env)) {
-        opts.set_max_background_compactions(n.decode()?);
+    if let Some(universal_opts) = arg.get(atoms::universal_compaction_options()) {
+        let universal_opts = decode_universal_compaction_options(universal_opts.options()?)?;
+        opts.set_universal_compaction_options(&universal_opts);
     }
 
-    if let Ok(n) = arg.map_get(atoms::max_background_flushes().to_term(env)) {
-        opts.set_max_background_flushes(n.decode()?);
+    if let Some(fifo_opts) = arg.get(atoms::fifo_compaction_options()) {
+        let mut fifo_opts = fifo_opts.options()?;
+
+        if let Some(max_size) = fifo_opts.get(atoms::max_table_files_size()) {
+            let mut fifo_compact_opts = FifoCompactOptions::default();
+            fifo_compact_opts.set_max_table_files_size(max_size.decode()?);
+            opts.set_fifo_compaction_options(&fifo_compact_opts);
+        }
+
+        // Files older than the TTL are dropped by FIFO compactions
+        if let Some(ttl) = fifo_opts.get(atoms::ttl()) {
+            let ttl: u64 = ttl.decode()?;
+            deferred.push(("ttl", ttl.to_string()));
+        }
+
+        fifo_opts.finish()?;
     }
 
-    if let Ok(disable) = arg.map_get(atoms::disable_auto_compactions().to_term(env)) {
-        opts.set_disable_auto_compactions(disable.decode()?);
+    if let Some(disable_opt) = arg.get(atoms::disable_auto_compactions()) {
+        let disable: bool = disable_opt.decode()?;
+
+        // Turning automatic compactions back on would silently undo `compaction_style: :none`
+        if manual_compactions_only && !disable {
+            return Err(disable_opt.invalid());
+        }
+
+        opts.set_disable_auto_compactions(disable);
     }
 
-    if let Ok(bg_io_stats) = arg.map_get(atoms::report_bg_io_stats().to_term(env)) {
+    if let Some(bg_io_stats) = arg.get(atoms::report_bg_io_stats()) {
         opts.set_report_bg_io_stats(bg_io_stats.decode()?);
     }
 
-    // Todo: set WAL Recovery Mode
-
-    if let Ok(num_levels) = arg.map_get(atoms::num_levels().to_term(env)) {
+    if let Some(num_levels) = arg.get(atoms::num_levels()) {
         opts.set_num_levels(num_levels.decode()?);
     }
 
-    if let Ok(enabled) = arg.map_get(atoms::use_direct_io_for_flush_and_compaction().to_term(env)) {
-        opts.set_use_direct_io_for_flush_and_compaction(enabled.decode()?);
+    if let Some(extractor) = arg.get(atoms::prefix_extractor()) {
+        let prefix_extractor: PrefixExtractor = extractor.decode()?;
+        prefix_extractor.apply(opts, &mut deferred);
+    }
+
+    if let Some(ratio) = arg.get(atoms::memtable_prefix_bloom_ratio()) {
+        opts.set_memtable_prefix_bloom_ratio(ratio.decode()?);
+    }
+
+    if let Some(operator) = arg.get(atoms::merge_operator()) {
+        let merge_operator: MergeOperatorType = operator.decode()?;
+        merge_operator.apply(opts);
+    }
+
+    if let Some(table_opts) = arg.get(atoms::block_based_table_options()) {
+        let block_opts = decode_block_based_options(table_opts.options()?)?;
+        opts.set_block_based_table_factory(&block_opts);
+    }
+
+    Ok(deferred)
+}
+
+///
+/// Decodes the `level` and `max_dict_bytes` of a compression options map, defaulting to the
+/// compression library's own level and to no dictionary.
+///
+fn decode_compression_options<'a>(mut arg: OptionMap<'a>) -> OptionResult<'a, (i32, i32)> {
+    let level = match arg.get(atoms::level()) {
+        Some(level) => level.decode()?,
+        None => DEFAULT_COMPRESSION_LEVEL,
+    };
+
+    let max_dict_bytes = match arg.get(atoms::max_dict_bytes()) {
+        Some(max_dict_bytes) => max_dict_bytes.decode()?,
+        None => 0,
+    };
+
+    arg.finish()?;
+    Ok((level, max_dict_bytes))
+}
+
+fn decode_universal_compaction_options<'a>(
+    mut arg: OptionMap<'a>,
+) -> OptionResult<'a, UniversalCompactOptions> {
+    let mut opts = UniversalCompactOptions::default();
+
+    if let Some(ratio) = arg.get(atoms::size_ratio()) {
+        opts.set_size_ratio(ratio.decode()?);
+    }
+
+    if let Some(width) = arg.get(atoms::min_merge_width()) {
+        opts.set_min_merge_width(width.decode()?);
+    }
+
+    if let Some(width) = arg.get(atoms::max_merge_width()) {
+        opts.set_max_merge_width(width.decode()?);
+    }
+
+    if let Some(percent) = arg.get(atoms::max_size_amplification_percent()) {
+        opts.set_max_size_amplification_percent(percent.decode()?);
+    }
+
+    arg.finish()?;
+    Ok(opts)
+}
+
+fn decode_block_based_options<'a>(mut arg: OptionMap<'a>) -> OptionResult<'a, BlockBasedOptions> {
+    let mut opts = BlockBasedOptions::default();
+
+    if let Some(size) = arg.get(atoms::block_size()) {
+        let i_size: u64 = size.decode()?;
+        opts.set_block_size(i_size as usize);
+    }
+
+    if let Some(size) = arg.get(atoms::block_cache_size()) {
+        let i_size: u64 = size.decode()?;
+        opts.set_block_cache(&Cache::new_lru_cache(i_size as usize));
+    }
+
+    if let Some(cache) = arg.get(atoms::block_cache()) {
+        let cache_arc: ResourceArc<CacheHandle> = cache.decode()?;
+        opts.set_block_cache(&cache_arc.cache);
+    }
+
+    // Takes precedence over `block_cache_size` and `block_cache`
+    if let Some(no_block_cache) = arg.get(atoms::no_block_cache()) {
+        if no_block_cache.decode()? {
+            opts.disable_cache();
+        }
+    }
+
+    if let Some(bits_per_key) = arg.get(atoms::bloom_filter_policy()) {
+        // Full filters rather than the legacy block based ones
+        let bits_per_key: i32 = bits_per_key.decode()?;
+        opts.set_bloom_filter(bits_per_key as f64, false);
     }
 
+    if let Some(enabled) = arg.get(atoms::cache_index_and_filter_blocks()) {
+        opts.set_cache_index_and_filter_blocks(enabled.decode()?);
+    }
+
+    if let Some(version) = arg.get(atoms::format_version()) {
+        opts.set_format_version(version.decode()?);
+    }
+
+    arg.finish()?;
     Ok(opts)
 }
 
@@ -537,6 +1568,14 @@ fn decode_iterator_mode<'a>(arg: Term<'a>) -> NifResult<IteratorMode<'a>> {
         }
 
         TermType::Tuple => {
+            // Ranges always start from the first key, with the bounds themselves being enforced
+            // through the read options (see `decode_iterator_range`)
+            if let Ok((atom_range, _, _)) = arg.decode::<(Atom, Term, Term)>() {
+                if atom_range == atoms::range() {
+                    return Ok(IteratorMode::Start);
+                }
+            }
+
             let (atom_from, key, atom_dir): (Atom, Binary, Atom) = arg.decode()?;
 
             if atom_from == atoms::from()
@@ -553,114 +1592,581 @@ fn decode_iterator_mode<'a>(arg: Term<'a>) -> NifResult<IteratorMode<'a>> {
             }
         }
 
-        _ => Err(Error::BadArg),
+        _ => Err(Error::BadArg),
+    }
+}
+
+fn decode_range_bound(arg: Term, inclusive_by_default: bool) -> NifResult<(Vec<u8>, bool)> {
+    if let Ok(key) = arg.decode::<Binary>() {
+        return Ok((key.as_slice().to_vec(), inclusive_by_default));
+    }
+
+    let (atom_kind, key): (Atom, Binary) = arg.decode()?;
+
+    if atom_kind == atoms::inclusive() {
+        Ok((key.as_slice().to_vec(), true))
+    } else if atom_kind == atoms::exclusive() {
+        Ok((key.as_slice().to_vec(), false))
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+///
+/// Decodes a `{:range, from, to}` iterator mode into a `(lower, upper)` pair suitable for
+/// `ReadOptions::set_iterate_lower_bound` and `ReadOptions::set_iterate_upper_bound`.
+///
+/// RocksDB treats the lower bound as inclusive and the upper bound as exclusive, so the other
+/// variants are mapped onto those by appending a null byte, which yields the smallest key that
+/// sorts after the given one.
+///
+/// Returns `None` for any other iterator mode.
+///
+fn decode_iterator_range(arg: Term) -> NifResult<Option<(Vec<u8>, Vec<u8>)>> {
+    let (atom_range, from, to): (Atom, Term, Term) = match arg.decode() {
+        Ok(range) => range,
+        Err(_) => return Ok(None),
+    };
+
+    if atom_range != atoms::range() {
+        return Ok(None);
+    }
+
+    let (mut lower, lower_inclusive) = decode_range_bound(from, true)?;
+    let (mut upper, upper_inclusive) = decode_range_bound(to, false)?;
+
+    if !lower_inclusive {
+        lower.push(0);
+    }
+
+    if upper_inclusive {
+        upper.push(0);
+    }
+
+    Ok(Some((lower, upper)))
+}
+
+///
+/// Decodes the options of a single column family, which are shared between `open` and
+/// `create_cf`. Options which apply to the database as a whole are rejected as unknown.
+///
+fn decode_cf_options<'a>(env: Env<'a>, arg: Term<'a>) -> OptionResult<'a, DecodedOptions> {
+    let mut arg = OptionMap::new(env, arg);
+    let mut opts = Options::default();
+
+    let deferred = decode_cf_options_into(&mut arg, &mut opts)?;
+
+    arg.finish()?;
+    Ok(DecodedOptions { opts, deferred })
+}
+
+///
+/// Decodes a column family to open, given either as a name or as a `{name, cf_opts}` tuple, into
+/// its name and options. Plain names have no options of their own, and are opened with the
+/// database's options instead.
+///
+fn decode_cf_descriptor<'a>(arg: Term<'a>) -> NifResult<(String, Option<Term<'a>>)> {
+    match arg.decode::<(String, Term<'a>)>() {
+        Ok((name, cf_opts)) => Ok((name, Some(cf_opts))),
+        Err(_) => Ok((arg.decode()?, None)),
+    }
+}
+
+fn open<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let path: &Path = Path::new(args[0].decode()?);
+
+    let db_opts = if args[1].map_size()? > 0 {
+        handle_option_error!(env, decode_db_options(env, args[1]))
+    } else {
+        DecodedOptions::default()
+    };
+
+    let db: DB = if args[2].list_length()? == 0 {
+        let db = handle_error!(env, DB::open(&db_opts.opts, path));
+        handle_error!(env, set_deferred_options(&db, None, &db_opts.deferred));
+        db
+    } else {
+        let iter: ListIterator = args[2].decode()?;
+        let mut cfs: Vec<ColumnFamilyDescriptor> = vec![];
+        let mut deferred: Vec<(String, Vec<(&'static str, String)>)> = vec![];
+        let mut has_default_cf = false;
+
+        for descriptor in iter {
+            let (name, cf_opts) = decode_cf_descriptor(descriptor)?;
+            // Column families without options of their own share the database's, rather than
+            // decoding them again, so that they share its block cache as well
+            let cf_opts = match cf_opts {
+                Some(cf_opts) => handle_option_error!(env, decode_cf_options(env, cf_opts)),
+                None => db_opts.clone(),
+            };
+            has_default_cf = has_default_cf || name == DEFAULT_COLUMN_FAMILY_NAME;
+            cfs.push(ColumnFamilyDescriptor::new(name.as_str(), cf_opts.opts));
+            deferred.push((name, cf_opts.deferred));
+        }
+
+        // rust-rocksdb would otherwise open the default column family with the default options
+        if !has_default_cf {
+            let default_opts = db_opts.clone();
+            cfs.push(ColumnFamilyDescriptor::new(
+                DEFAULT_COLUMN_FAMILY_NAME,
+                default_opts.opts,
+            ));
+            deferred.push((
+                DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+                default_opts.deferred,
+            ));
+        }
+
+        let db = handle_error!(env, DB::open_cf_descriptors(&db_opts.opts, path, cfs));
+
+        for (name, cf_deferred) in deferred {
+            let cf = db.cf_handle(&name);
+            handle_error!(env, set_deferred_options(&db, cf.as_ref(), &cf_deferred));
+        }
+
+        db
+    };
+
+    let resp = (
+        atoms::ok(),
+        ResourceArc::new(DBHandle {
+            db: Arc::new(Database::new(db)),
+        }),
+    ).encode(env);
+
+    Ok(resp)
+}
+
+fn close<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let mut db_guard = db_arc.db.write().unwrap();
+
+    // Closing an already closed database is a no-op
+    if let Some(ref db) = *db_guard {
+        handle_error!(env, db.flush());
+    } else {
+        return Ok(atoms::ok().encode(env));
+    }
+
+    db_arc.db.release_dependents();
+
+    let db = db_guard.take().unwrap();
+    drop(db_guard);
+
+    // Compactions may still be running through a shared reference (see `Database::shared`), which
+    // are cancelled rather than waited for
+    db.cancel_all_background_work(true);
+    db_arc.db.wait_for_shared(&db);
+
+    // Dropping the DB closes it, and releases the lock on its directory
+    drop(db);
+
+    Ok(atoms::ok().encode(env))
+}
+
+fn create_snapshot<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let resp = (
+        atoms::ok(),
+        ResourceArc::new(SnapshotHandle::new(&db_arc.db, db)),
+    ).encode(env);
+
+    Ok(resp)
+}
+
+fn create_cache<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let cache_type: Atom = args[0].decode()?;
+    let capacity: u64 = args[1].decode()?;
+
+    let cache = if cache_type == atoms::lru() {
+        Cache::new_lru_cache(capacity as usize)
+    } else if cache_type == atoms::clock() {
+        // An estimated entry charge of 0 lets RocksDB size the table on its own
+        Cache::new_hyper_clock_cache(capacity as usize, 0)
+    } else {
+        return Err(Error::BadArg);
+    };
+
+    Ok((atoms::ok(), ResourceArc::new(CacheHandle { cache })).encode(env))
+}
+
+fn create_write_buffer_manager<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let buffer_size: u64 = args[0].decode()?;
+    let allow_stall: bool = args[1].decode()?;
+
+    let manager = match args[2].decode::<Option<ResourceArc<CacheHandle>>>()? {
+        // Memtable memory is charged to the cache, so both share a single budget
+        Some(cache_arc) => WriteBufferManager::new_write_buffer_manager_with_cache(
+            buffer_size as usize,
+            allow_stall,
+            cache_arc.cache.clone(),
+        ),
+        None => WriteBufferManager::new_write_buffer_manager(buffer_size as usize, allow_stall),
+    };
+
+    let resp = (
+        atoms::ok(),
+        ResourceArc::new(WriteBufferManagerHandle { manager }),
+    ).encode(env);
+
+    Ok(resp)
+}
+
+fn count<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let count = handle_error!(env, db.property_int_value("rocksdb.estimate-num-keys"));
+
+    Ok(count.unwrap_or(0).encode(env))
+}
+
+fn count_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let cf = &handle_dropped!(env, cf_arc.get(db));
+
+    let count = handle_error!(
+        env,
+        db.property_int_value_cf(cf, "rocksdb.estimate-num-keys")
+    );
+
+    Ok(count.unwrap_or(0).encode(env))
+}
+
+///
+/// Counts the entries visible to `iter`, starting from the position described by `mode`, up to
+/// `COUNT_CHUNK_SIZE` of them. Returns the count along with the key to resume counting from, if
+/// there are entries left.
+///
+/// Uses the raw iterator so that keys and values never get copied out of RocksDB.
+///
+fn count_raw(mut iter: DBRawIterator, mode: IteratorMode) -> (u64, Option<Vec<u8>>) {
+    match mode {
+        IteratorMode::Start => iter.seek_to_first(),
+        IteratorMode::End => iter.seek_to_last(),
+        IteratorMode::From(key, Direction::Forward) => iter.seek(key),
+        IteratorMode::From(key, Direction::Reverse) => iter.seek_for_prev(key),
+    }
+
+    let reverse = match count_direction(mode) {
+        Direction::Forward => false,
+        Direction::Reverse => true,
+    };
+
+    let mut count = 0;
+    while iter.valid() {
+        if count == COUNT_CHUNK_SIZE {
+            return (count, iter.key().map(|key| key.to_vec()));
+        }
+
+        count += 1;
+
+        if reverse {
+            iter.prev();
+        } else {
+            iter.next();
+        }
+    }
+
+    (count, None)
+}
+
+fn count_direction(mode: IteratorMode) -> Direction {
+    match mode {
+        IteratorMode::Start | IteratorMode::From(_, Direction::Forward) => Direction::Forward,
+        IteratorMode::End | IteratorMode::From(_, Direction::Reverse) => Direction::Reverse,
+    }
+}
+
+fn count_read_options(range: &Option<(Vec<u8>, Vec<u8>)>) -> ReadOptions {
+    let mut read_opts = ReadOptions::default();
+    if let Some((ref lower, ref upper)) = *range {
+        read_opts.set_iterate_lower_bound(lower.clone());
+        read_opts.set_iterate_upper_bound(upper.clone());
+    }
+
+    // Bulk counts shouldn't evict the working set from the block cache
+    read_opts.fill_cache(false);
+    read_opts
+}
+
+fn count_exact<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let mode = decode_iterator_mode(args[1])?;
+    let range = decode_iterator_range(args[1])?;
+
+    // The keys are counted `COUNT_CHUNK_SIZE` at a time, with the locks let go of in between so
+    // that the database can be closed in the middle of a long count. Counting from a snapshot
+    // keeps the chunks consistent with each other, so databases get a snapshot of their own.
+    let snapshot_wrapper = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            SnapshotHandle::new(&db_arc.db, db).snapshot
+        }
+        Err(_) => {
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            snapshot_arc.snapshot.clone()
+        }
+    };
+
+    let mut count = 0;
+    let mut resume: Option<Vec<u8>> = None;
+
+    loop {
+        let chunk_mode = match resume {
+            Some(ref key) => IteratorMode::From(key, count_direction(mode)),
+            None => mode,
+        };
+
+        let snapshot_guard = snapshot_wrapper.snapshot.read().unwrap();
+        let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+        let (chunk, next) = count_raw(
+            snapshot.raw_iterator_opt(count_read_options(&range)),
+            chunk_mode,
+        );
+
+        count += chunk;
+        match next {
+            Some(key) => resume = Some(key),
+            None => break,
+        }
+    }
+
+    Ok(count.encode(env))
+}
+
+fn count_exact_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let mode = decode_iterator_mode(args[2])?;
+    let range = decode_iterator_range(args[2])?;
+
+    // See `count_exact`
+    let snapshot_wrapper = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            SnapshotHandle::new(&db_arc.db, db).snapshot
+        }
+        Err(_) => {
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            snapshot_arc.snapshot.clone()
+        }
+    };
+
+    let mut count = 0;
+    let mut resume: Option<Vec<u8>> = None;
+
+    loop {
+        let chunk_mode = match resume {
+            Some(ref key) => IteratorMode::From(key, count_direction(mode)),
+            None => mode,
+        };
+
+        // The database stays read locked to keep the column family from being dropped
+        let db_guard = snapshot_wrapper.db.read().unwrap();
+        let db = handle_closed!(env, db_guard.as_ref());
+        let snapshot_guard = snapshot_wrapper.snapshot.read().unwrap();
+        let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+        let cf = handle_dropped!(env, cf_arc.get(db));
+        let (chunk, next) = count_raw(
+            snapshot.raw_iterator_cf_opt(&cf, count_read_options(&range)),
+            chunk_mode,
+        );
+
+        count += chunk;
+        match next {
+            Some(key) => resume = Some(key),
+            None => break,
+        }
     }
+
+    Ok(count.encode(env))
 }
 
-fn open<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-    let path: &Path = Path::new(args[0].decode()?);
+///
+/// Runs `compact` on the calling dirty scheduler, or, when `notify` is a reference rather than
+/// `nil`, on a separate thread. In the latter case `{:rox_compaction_complete, notify}` is sent to
+/// the calling process once the compaction is done.
+///
+fn run_compaction<'a, F>(env: Env<'a>, notify: Term<'a>, compact: F) -> NifResult<Term<'a>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    if atoms::nil() == notify {
+        compact();
+        return Ok(atoms::ok().encode(env));
+    }
 
-    let db_opts = if args[1].map_size()? > 0 {
-        decode_db_options(env, args[1])?
-    } else {
-        Options::default()
-    };
+    let pid = env.pid();
+    let mut msg_env = OwnedEnv::new();
+    let reference = msg_env.save(notify);
 
-    let cf: Vec<&str> = if args[2].list_length()? == 0 {
-        vec![]
-    } else {
-        let iter: ListIterator = args[2].decode()?;
-        let result: Vec<&str> = try!(iter.map(|x| x.decode()).collect::<NifResult<Vec<&str>>>());
+    thread::spawn(move || {
+        compact();
 
-        result
-    };
+        msg_env.send_and_clear(&pid, |env| {
+            (atoms::rox_compaction_complete(), reference.load(env)).encode(env)
+        });
+    });
 
-    let db: DB = handle_error!(env, DB::open_cf(&db_opts, path, &cf));
+    Ok(atoms::ok().encode(env))
+}
 
-    let resp = (
-        atoms::ok(),
-        ResourceArc::new(DBHandle {
-            db: Arc::new(RwLock::new(db)),
-        }),
-    ).encode(env);
+fn compact_range<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
 
-    Ok(resp)
+    let start: Option<Vec<u8>> = args[1]
+        .decode::<Option<Binary>>()?
+        .map(|key| key.as_slice().to_vec());
+    let end: Option<Vec<u8>> = args[2]
+        .decode::<Option<Binary>>()?
+        .map(|key| key.as_slice().to_vec());
+
+    let opts = handle_option_error!(env, decode_compact_options(env, args[3]));
+
+    // Compactions can take a long time, so they hold a reference to the `DB` rather than the lock
+    // on it, which would keep the database from being closed in the meantime
+    let db = handle_closed!(env, db_arc.db.shared());
+
+    run_compaction(env, args[4], move || {
+        db.compact_range_opt(start.as_ref(), end.as_ref(), &opts);
+    })
 }
 
-fn create_snapshot<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+fn compact_range_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db_handle = db_arc.deref();
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
 
-    let resp = (
-        atoms::ok(),
-        ResourceArc::new(SnapshotHandle::new(db_handle)),
-    ).encode(env);
+    let start: Option<Vec<u8>> = args[2]
+        .decode::<Option<Binary>>()?
+        .map(|key| key.as_slice().to_vec());
+    let end: Option<Vec<u8>> = args[3]
+        .decode::<Option<Binary>>()?
+        .map(|key| key.as_slice().to_vec());
 
-    Ok(resp)
+    let opts = handle_option_error!(env, decode_compact_options(env, args[4]));
+
+    // See `compact_range`
+    let db = handle_closed!(env, db_arc.db.shared());
+    let cf = handle_dropped!(env, cf_arc.get(&db));
+
+    // The column family borrows from the `DB`, which the compaction keeps alive through its own
+    // reference instead (see `IteratorHandle::new`). A column family which is dropped in the
+    // meantime stays alive until the compaction is done.
+    let cf: Arc<BoundColumnFamily<'static>> = unsafe { std::mem::transmute(cf) };
+
+    run_compaction(env, args[5], move || {
+        db.compact_range_cf_opt(&cf, start.as_ref(), end.as_ref(), &opts);
+
+        // The column family has to go before the reference to the `DB` does
+        drop(cf);
+    })
 }
 
-fn count<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+fn get_property<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db_handle = db_arc.deref();
-
-    let iterator = db_handle.db.read().unwrap().iterator(IteratorMode::Start);
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
-    let count = iterator.count();
+    let name: &str = args[1].decode()?;
 
-    Ok((count as u64).encode(env))
+    match handle_error!(env, db.property_value(name)) {
+        Some(value) => Ok((atoms::ok(), value).encode(env)),
+        None => Ok(atoms::not_found().encode(env)),
+    }
 }
 
-fn count_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+fn get_property_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db_handle = db_arc.deref();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
-    let cf = cf_arc.deref().cf;
+    let cf = &handle_dropped!(env, cf_arc.get(db));
+    let name: &str = args[2].decode()?;
 
-    let iterator = handle_error!(
-        env,
-        db_handle
-            .db
-            .read()
-            .unwrap()
-            .iterator_cf(cf, IteratorMode::Start)
-    );
+    match handle_error!(env, db.property_value_cf(cf, name)) {
+        Some(value) => Ok((atoms::ok(), value).encode(env)),
+        None => Ok(atoms::not_found().encode(env)),
+    }
+}
+
+fn get_int_property<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let name: &str = args[1].decode()?;
+
+    match handle_error!(env, db.property_int_value(name)) {
+        Some(value) => Ok((atoms::ok(), value).encode(env)),
+        None => Ok(atoms::not_found().encode(env)),
+    }
+}
+
+fn get_int_property_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
-    let count = iterator.count();
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let cf = &handle_dropped!(env, cf_arc.get(db));
+    let name: &str = args[2].decode()?;
 
-    Ok((count as u64).encode(env))
+    match handle_error!(env, db.property_int_value_cf(cf, name)) {
+        Some(value) => Ok((atoms::ok(), value).encode(env)),
+        None => Ok(atoms::not_found().encode(env)),
+    }
 }
 
 fn create_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let mut db = db_arc.db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let name: &str = args[1].decode()?;
-    let has_db_opts = args[2].map_size()? > 0;
-    let opts = if has_db_opts {
-        decode_db_options(env, args[2])?
-    } else {
-        Options::default()
-    };
+    let opts = handle_option_error!(env, decode_cf_options(env, args[2]));
 
-    let cf = handle_error!(env, db.create_cf(name, &opts));
+    handle_error!(env, db.create_cf(name, &opts.opts));
+    handle_error!(
+        env,
+        set_deferred_options(db, db.cf_handle(name).as_ref(), &opts.deferred)
+    );
 
     let resp = (
         atoms::ok(),
-        ResourceArc::new(CFHandle {
-            cf: cf,
-            db: DatabaseRef::from(&db_arc.db),
-        }),
+        ResourceArc::new(CFHandle::new(name, DatabaseRef::from(&db_arc.db))),
     ).encode(env);
 
     Ok(resp)
 }
 
+fn drop_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.write().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let name: &str = args[1].decode()?;
+
+    handle_error!(env, db.drop_cf(name));
+    db_arc.db.mark_cf_dropped(name);
+
+    Ok(atoms::ok().encode(env))
+}
+
 fn list_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let path: &Path = Path::new(args[0].decode()?);
 
     let db_opts = if args[1].map_size()? > 0 {
-        decode_db_options(env, args[1])?
+        handle_option_error!(env, decode_db_options(env, args[1])).opts
     } else {
         Options::default()
     };
@@ -673,125 +2179,197 @@ fn list_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn cf_handle<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db = db_arc.db.read().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let name: &str = args[1].decode()?;
 
-    match db.cf_handle(name) {
-        Some(cf) => Ok((
+    if db.cf_handle(name).is_some() {
+        Ok((
             atoms::ok(),
-            ResourceArc::new(CFHandle {
-                cf: cf,
-                db: DatabaseRef::from(&db_arc.db),
-            }),
-        ).encode(env)),
-        None => Ok((
+            ResourceArc::new(CFHandle::new(name, DatabaseRef::from(&db_arc.db))),
+        ).encode(env))
+    } else {
+        Ok((
             atoms::error(),
             format!("Could not find ColumnFamily named {}", name),
-        ).encode(env)),
+        ).encode(env))
+    }
+}
+
+///
+/// The single path every write NIF goes through: decodes the `write_options` which every write
+/// NIF takes as its last argument, and writes `batch` with them.
+///
+/// Single writes are written as batches of one, which is how RocksDB implements them anyway.
+///
+/// Writes with `no_slowdown` fail with `Incomplete` rather than waiting out a write stall, which
+/// is returned as `{:error, :incomplete}` so that callers can back off.
+///
+fn write_batch<'a>(
+    env: Env<'a>,
+    db: &DB,
+    batch: WriteBatch,
+    args: &[Term<'a>],
+) -> NifResult<Term<'a>> {
+    let write_opts = handle_option_error!(env, decode_write_options(env, args[args.len() - 1]));
+
+    match db.write_opt(batch, &write_opts) {
+        Ok(_) => Ok(atoms::ok().encode(env)),
+        Err(ref err) if err.kind() == ErrorKind::Incomplete => {
+            Ok((atoms::error(), atoms::incomplete()).encode(env))
+        }
+        Err(err) => Ok((atoms::error(), err.to_string().encode(env)).encode(env)),
     }
 }
 
 fn put<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db = db_arc.deref().db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let key: Binary = args[1].decode()?;
     let val: Binary = args[2].decode()?;
 
-    let resp = if args[3].map_size()? > 0 {
-        let write_opts = decode_write_options(env, args[2])?;
-        db.put_opt(key.as_slice(), val.as_slice(), &write_opts)
-    } else {
-        db.put(key.as_slice(), val.as_slice())
-    };
-
-    handle_error!(env, resp);
+    let mut batch = WriteBatch::default();
+    batch.put(key.as_slice(), val.as_slice());
 
-    Ok(atoms::ok().encode(env))
+    write_batch(env, db, batch, args)
 }
 
 fn put_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db = db_arc.deref().db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
-    let cf = cf_arc.deref().cf;
+    let cf = handle_dropped!(env, cf_arc.get(db));
 
     let key: Binary = args[2].decode()?;
     let val: Binary = args[3].decode()?;
 
-    let resp = if args[4].map_size()? > 0 {
-        let write_opts = decode_write_options(env, args[2])?;
-        db.put_cf_opt(cf, key.as_slice(), val.as_slice(), &write_opts)
-    } else {
-        db.put_cf(cf, key.as_slice(), val.as_slice())
-    };
-
-    handle_error!(env, resp);
+    let mut batch = WriteBatch::default();
+    batch.put_cf(&cf, key.as_slice(), val.as_slice());
 
-    Ok(atoms::ok().encode(env))
+    write_batch(env, db, batch, args)
 }
 
 fn delete<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db = db_arc.deref().db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let key: Binary = args[1].decode()?;
 
-    let resp = if args[2].map_size()? > 0 {
-        let write_opts = decode_write_options(env, args[2])?;
-        db.delete_opt(key.as_slice(), &write_opts)
-    } else {
-        db.delete(key.as_slice())
-    };
-
-    handle_error!(env, resp);
+    let mut batch = WriteBatch::default();
+    batch.delete(key.as_slice());
 
-    Ok(atoms::ok().encode(env))
+    write_batch(env, db, batch, args)
 }
 
 fn delete_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
-    let db = db_arc.deref().db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
-    let cf = cf_arc.deref().cf;
+    let cf = handle_dropped!(env, cf_arc.get(db));
 
     let key: Binary = args[2].decode()?;
 
-    let resp = if args[3].map_size()? > 0 {
-        let write_opts = decode_write_options(env, args[3])?;
-        db.delete_cf_opt(cf, key.as_slice(), &write_opts)
-    } else {
-        db.delete_cf(cf, key.as_slice())
-    };
+    let mut batch = WriteBatch::default();
+    batch.delete_cf(&cf, key.as_slice());
 
-    handle_error!(env, resp);
+    write_batch(env, db, batch, args)
+}
 
-    Ok(atoms::ok().encode(env))
+fn delete_range<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let from: Binary = args[1].decode()?;
+    let to: Binary = args[2].decode()?;
+
+    let mut batch = WriteBatch::default();
+    batch.delete_range(from.as_slice(), to.as_slice());
+
+    write_batch(env, db, batch, args)
+}
+
+fn delete_range_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let cf = handle_dropped!(env, cf_arc.get(db));
+
+    let from: Binary = args[2].decode()?;
+    let to: Binary = args[3].decode()?;
+
+    let mut batch = WriteBatch::default();
+    batch.delete_range_cf(&cf, from.as_slice(), to.as_slice());
+
+    write_batch(env, db, batch, args)
+}
+
+fn merge<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let key: Binary = args[1].decode()?;
+    let val: Binary = args[2].decode()?;
+
+    let mut batch = WriteBatch::default();
+    batch.merge(key.as_slice(), val.as_slice());
+
+    write_batch(env, db, batch, args)
+}
+
+fn merge_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let db_arc: ResourceArc<DBHandle> = args[0].decode()?;
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
+
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let cf = handle_dropped!(env, cf_arc.get(db));
+
+    let key: Binary = args[2].decode()?;
+    let val: Binary = args[3].decode()?;
+
+    let mut batch = WriteBatch::default();
+    batch.merge_cf(&cf, key.as_slice(), val.as_slice());
+
+    write_batch(env, db, batch, args)
 }
 
 fn get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let key = args[1].decode::<Binary>()?.as_slice();
 
-    let resp = args[0]
-        .decode::<ResourceArc<DBHandle>>()
-        .map(|db_arc| {
-            let db = db_arc.db.read().unwrap();
-            db.get(key)
-        })
-        .or_else(|_| {
+    let read_opts = handle_option_error!(env, decode_read_options(env, args[2]));
+
+    let resp = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            db.get_opt(key, &read_opts)
+        }
+        Err(_) => {
             let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
-            Ok(snapshot_arc.get(key))
-        })?;
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            snapshot.get_opt(key, read_opts)
+        }
+    };
 
     let val_option = handle_error!(env, resp);
 
     match val_option {
         Some(val) => {
             let mut bin = OwnedBinary::new(val.len()).unwrap();
-            bin.as_mut_slice().write(&val).unwrap();
+            bin.as_mut_slice().write_all(&val).unwrap();
 
             Ok((atoms::ok(), bin.release(env).encode(env)).encode(env))
         }
@@ -800,26 +2378,36 @@ fn get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 }
 
 fn get_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-    let cf = args[1].decode::<ResourceArc<CFHandle>>()?.cf;
+    let cf_arc = args[1].decode::<ResourceArc<CFHandle>>()?;
     let key = args[2].decode::<Binary>()?.as_slice();
 
-    let resp = args[0]
-        .decode::<ResourceArc<DBHandle>>()
-        .map(|db_arc| {
-            let db = db_arc.db.read().unwrap();
-            db.get_cf(cf, key)
-        })
-        .or_else(|_| {
+    let read_opts = handle_option_error!(env, decode_read_options(env, args[3]));
+
+    let resp = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let cf = handle_dropped!(env, cf_arc.get(db));
+            db.get_cf_opt(&cf, key, &read_opts)
+        }
+        Err(_) => {
             let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
-            Ok(snapshot_arc.get_cf(cf, key))
-        })?;
+            // The database stays read locked to keep the column family from being dropped
+            let db_guard = snapshot_arc.snapshot.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            let cf = handle_dropped!(env, cf_arc.get(db));
+            snapshot.get_cf_opt(&cf, key, read_opts)
+        }
+    };
 
     let val_option = handle_error!(env, resp);
 
     match val_option {
         Some(val) => {
             let mut bin = OwnedBinary::new(val.len()).unwrap();
-            bin.as_mut_slice().write(&val).unwrap();
+            bin.as_mut_slice().write_all(&val).unwrap();
 
             Ok((atoms::ok(), bin.release(env).encode(env)).encode(env))
         }
@@ -827,102 +2415,300 @@ fn get_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     }
 }
 
+fn encode_get_result<'a>(
+    env: Env<'a>,
+    result: Result<Option<Vec<u8>>, rocksdb::Error>,
+) -> Term<'a> {
+    match result {
+        Ok(Some(val)) => (atoms::ok(), encode_binary(env, &val)).encode(env),
+        Ok(None) => atoms::not_found().encode(env),
+        Err(err) => (atoms::error(), err.to_string()).encode(env),
+    }
+}
+
+///
+/// Resolves the column families of `keys`, where `None` stands for the default column family, or
+/// returns `None` if any of them has been dropped.
+///
+fn live_cf_keys<'d, 'b>(
+    db: &'d DB,
+    keys: &'b [(Option<ResourceArc<CFHandle>>, Binary)],
+) -> Option<Vec<(Arc<BoundColumnFamily<'d>>, &'b [u8])>> {
+    keys.iter()
+        .map(|(cf_arc, key)| {
+            let cf = match *cf_arc {
+                Some(ref cf_arc) => cf_arc.get(db),
+                None => db.cf_handle(DEFAULT_COLUMN_FAMILY_NAME),
+            };
+
+            cf.map(|cf| (cf, key.as_slice()))
+        })
+        .collect()
+}
+
+fn multi_get<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let keys: Vec<Binary> = args[1].decode()?;
+
+    let read_opts = handle_option_error!(env, decode_read_options(env, args[2]));
+
+    let results = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            db.multi_get_opt(keys.iter().map(|key| key.as_slice()), &read_opts)
+        }
+        Err(_) => {
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            snapshot.multi_get_opt(keys.iter().map(|key| key.as_slice()), read_opts)
+        }
+    };
+
+    let results: Vec<Term<'a>> = results
+        .into_iter()
+        .map(|result| encode_get_result(env, result))
+        .collect();
+
+    Ok(results.encode(env))
+}
+
+fn multi_get_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let keys: Vec<(Option<ResourceArc<CFHandle>>, Binary)> = args[1].decode()?;
+
+    let read_opts = handle_option_error!(env, decode_read_options(env, args[2]));
+
+    let results = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let cf_keys = handle_dropped!(env, live_cf_keys(db, &keys));
+            db.multi_get_cf_opt(cf_keys.iter().map(|(cf, key)| (cf, *key)), &read_opts)
+        }
+        Err(_) => {
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            // The database stays read locked to keep the column families from being dropped
+            let db_guard = snapshot_arc.snapshot.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            let cf_keys = handle_dropped!(env, live_cf_keys(db, &keys));
+            snapshot.multi_get_cf_opt(cf_keys.iter().map(|(cf, key)| (cf, *key)), read_opts)
+        }
+    };
+
+    let results: Vec<Term<'a>> = results
+        .into_iter()
+        .map(|result| encode_get_result(env, result))
+        .collect();
+
+    Ok(results.encode(env))
+}
+
+///
+/// Checks whether `key` exists without copying its value out of RocksDB. `key_may_exist` is asked
+/// first, which only consults the memtables and bloom filters and has no false negatives. Keys
+/// which may exist are then looked up with the value pinned in place rather than copied.
+///
+fn key_exists(
+    db: &DB,
+    cf: Option<&Arc<BoundColumnFamily>>,
+    key: &[u8],
+    read_opts: &ReadOptions,
+) -> Result<bool, rocksdb::Error> {
+    let may_exist = match cf {
+        Some(cf) => db.key_may_exist_cf_opt(cf, key, read_opts),
+        None => db.key_may_exist_opt(key, read_opts),
+    };
+
+    if !may_exist {
+        return Ok(false);
+    }
+
+    let value = match cf {
+        Some(cf) => db.get_pinned_cf_opt(cf, key, read_opts)?,
+        None => db.get_pinned_opt(key, read_opts)?,
+    };
+
+    Ok(value.is_some())
+}
+
+fn exists<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let key = args[1].decode::<Binary>()?.as_slice();
+
+    let mut read_opts = handle_option_error!(env, decode_read_options(env, args[2]));
+
+    let resp = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            key_exists(db, None, key, &read_opts)
+        }
+        Err(_) => {
+            // Snapshots can't check for keys on their own, so the lookup goes through the
+            // database with the snapshot set in the read options
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            let db_guard = snapshot_arc.snapshot.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            read_opts.set_snapshot(snapshot);
+            key_exists(db, None, key, &read_opts)
+        }
+    };
+
+    Ok(handle_error!(env, resp).encode(env))
+}
+
+fn exists_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let cf_arc: ResourceArc<CFHandle> = args[1].decode()?;
+    let key = args[2].decode::<Binary>()?.as_slice();
+
+    let mut read_opts = handle_option_error!(env, decode_read_options(env, args[3]));
+
+    let resp = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let cf = &handle_dropped!(env, cf_arc.get(db));
+            key_exists(db, Some(cf), key, &read_opts)
+        }
+        Err(_) => {
+            let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
+            let db_guard = snapshot_arc.snapshot.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            let cf = &handle_dropped!(env, cf_arc.get(db));
+            read_opts.set_snapshot(snapshot);
+            key_exists(db, Some(cf), key, &read_opts)
+        }
+    };
+
+    Ok(handle_error!(env, resp).encode(env))
+}
+
 fn iterate<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-    let (iter, db_ref) = args[0]
-        .decode::<ResourceArc<DBHandle>>()
-        .and_then(|db_arc| {
-            let db = db_arc.db.read().unwrap();
-            Ok((
-                db.iterator(decode_iterator_mode(args[1])?),
+    let mode = decode_iterator_mode(args[1])?;
+
+    let mut read_opts = handle_option_error!(env, decode_read_options(env, args[2]));
+
+    if let Some((lower, upper)) = decode_iterator_range(args[1])? {
+        read_opts.set_iterate_lower_bound(lower);
+        read_opts.set_iterate_upper_bound(upper);
+    }
+
+    // The iterator takes ownership of its read options, so only one of the branches below may
+    // consume them. The iterator handle is created while the lock is still held, so that closing
+    // the database can't race with registering the iterator.
+    let iter_handle = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            IteratorHandle::new(
+                db.iterator_opt(mode, read_opts),
                 DatabaseRef::from(&db_arc.db),
-            ))
-        })
-        .or_else(|_| {
+            )
+        }
+        Err(_) => {
             let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
-            Ok((
-                snapshot_arc
-                    .snapshot
-                    .snapshot
-                    .as_ref()
-                    .unwrap()
-                    .iterator(decode_iterator_mode(args[1])?),
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            IteratorHandle::new(
+                snapshot.iterator_opt(mode, read_opts),
                 DatabaseRef::from(&snapshot_arc.snapshot),
-            ))
-        })?;
+            )
+        }
+    };
 
-    let resp = (
-        atoms::ok(),
-        ResourceArc::new(IteratorHandle {
-            iter: RwLock::new(iter),
-            db: db_ref,
-        }),
-    ).encode(env);
+    let resp = (atoms::ok(), ResourceArc::new(iter_handle)).encode(env);
 
     Ok(resp)
 }
 
 fn iterate_cf<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
-    let cf = args[1].decode::<ResourceArc<CFHandle>>()?.cf;
-
-    let (iter_res, db_ref) = args[0]
-        .decode::<ResourceArc<DBHandle>>()
-        .and_then(|db_arc| {
-            let db = db_arc.db.read().unwrap();
-            Ok((
-                db.iterator_cf(cf, decode_iterator_mode(args[2])?),
+    let cf_arc = args[1].decode::<ResourceArc<CFHandle>>()?;
+    let mode = decode_iterator_mode(args[2])?;
+
+    let mut read_opts = handle_option_error!(env, decode_read_options(env, args[3]));
+
+    if let Some((lower, upper)) = decode_iterator_range(args[2])? {
+        read_opts.set_iterate_lower_bound(lower);
+        read_opts.set_iterate_upper_bound(upper);
+    }
+
+    let iter_handle = match args[0].decode::<ResourceArc<DBHandle>>() {
+        Ok(db_arc) => {
+            let db_guard = db_arc.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let cf = handle_dropped!(env, cf_arc.get(db));
+            IteratorHandle::new(
+                db.iterator_cf_opt(&cf, read_opts, mode),
                 DatabaseRef::from(&db_arc.db),
-            ))
-        })
-        .or_else(|_| {
+            )
+        }
+        Err(_) => {
             let snapshot_arc = args[0].decode::<ResourceArc<SnapshotHandle>>()?;
-            Ok((
-                snapshot_arc
-                    .snapshot
-                    .snapshot
-                    .as_ref()
-                    .unwrap()
-                    .iterator_cf(cf, decode_iterator_mode(args[2])?),
+            // The database stays read locked to keep the column family from being dropped
+            let db_guard = snapshot_arc.snapshot.db.read().unwrap();
+            let db = handle_closed!(env, db_guard.as_ref());
+            let snapshot_guard = snapshot_arc.snapshot.snapshot.read().unwrap();
+            let snapshot = handle_closed!(env, snapshot_guard.as_ref());
+            let cf = handle_dropped!(env, cf_arc.get(db));
+            IteratorHandle::new(
+                snapshot.iterator_cf_opt(&cf, read_opts, mode),
                 DatabaseRef::from(&snapshot_arc.snapshot),
-            ))
-        })?;
-
-    let iter = handle_error!(env, iter_res);
+            )
+        }
+    };
 
-    let resp = (
-        atoms::ok(),
-        ResourceArc::new(IteratorHandle {
-            iter: RwLock::new(iter),
-            db: db_ref,
-        }),
-    ).encode(env);
+    let resp = (atoms::ok(), ResourceArc::new(iter_handle)).encode(env);
 
     Ok(resp)
 }
 
+fn encode_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    let mut bin = OwnedBinary::new(bytes.len()).unwrap();
+    bin.as_mut_slice().write_all(bytes).unwrap();
+
+    bin.release(env).encode(env)
+}
+
 fn iterator_next<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let iter_arc: ResourceArc<IteratorHandle> = args[0].decode()?;
-    let mut iter = iter_arc.iter.write().unwrap();
+    let mut iter_guard = iter_arc.iter.write().unwrap();
+    let iter = handle_closed!(env, iter_guard.as_mut());
 
     match iter.next() {
         None => Ok(atoms::done().encode(env)),
-        Some((key, val)) => {
-            let mut enc_key = OwnedBinary::new(key.len()).unwrap();
-            enc_key.as_mut_slice().write(&key).unwrap();
+        Some(row) => {
+            let (key, val) = handle_error!(env, row);
 
-            let mut enc_val = OwnedBinary::new(val.len()).unwrap();
-            enc_val.as_mut_slice().write(&val).unwrap();
-
-            Ok((
-                enc_key.release(env).encode(env),
-                enc_val.release(env).encode(env),
-            ).encode(env))
+            Ok((encode_binary(env, &key), encode_binary(env, &val)).encode(env))
         }
     }
 }
 
+fn iterator_next_n<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
+    let iter_arc: ResourceArc<IteratorHandle> = args[0].decode()?;
+    let n: usize = args[1].decode()?;
+
+    let mut iter_guard = iter_arc.iter.write().unwrap();
+    let iter = handle_closed!(env, iter_guard.as_mut());
+
+    let mut rows: Vec<Term<'a>> = Vec::with_capacity(n);
+    for row in iter.by_ref().take(n) {
+        let (key, val) = handle_error!(env, row);
+        rows.push((encode_binary(env, &key), encode_binary(env, &val)).encode(env));
+    }
+
+    Ok(rows.encode(env))
+}
+
 fn iterator_reset<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let iter_arc: ResourceArc<IteratorHandle> = args[0].decode()?;
-    let mut iter = iter_arc.iter.write().unwrap();
+    let mut iter_guard = iter_arc.iter.write().unwrap();
+    let iter = handle_closed!(env, iter_guard.as_mut());
 
     let iterator_mode = decode_iterator_mode(args[1])?;
 
@@ -933,50 +2719,91 @@ fn iterator_reset<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
 
 fn batch_write<'a>(env: Env<'a>, args: &[Term<'a>]) -> NifResult<Term<'a>> {
     let ops_iter: ListIterator = args[0].decode()?;
-    let ops: Vec<BatchOperation> = try!(
-        ops_iter
-            .map(|x| x.decode())
-            .collect::<NifResult<Vec<BatchOperation>>>()
-    );
+    let ops: Vec<BatchOperation> = ops_iter
+        .map(|x| x.decode())
+        .collect::<NifResult<Vec<BatchOperation>>>()?;
 
     let db_arc: ResourceArc<DBHandle> = args[1].decode()?;
-    let db = db_arc.db.write().unwrap();
+    let db_guard = db_arc.db.read().unwrap();
+    let db = handle_closed!(env, db_guard.as_ref());
 
     let mut batch = WriteBatch::default();
     for op in ops {
         match op {
-            BatchOperation::Put(key, val) => batch.put(key, val).unwrap(),
-            BatchOperation::PutCf(cf, key, val) => batch.put_cf(cf, key, val).unwrap(),
-            BatchOperation::Delete(key) => batch.delete(key).unwrap(),
-            BatchOperation::DeleteCf(cf, key) => batch.delete_cf(cf, key).unwrap(),
+            BatchOperation::Put(key, val) => batch.put(key, val),
+            BatchOperation::PutCf(cf_arc, key, val) => {
+                let cf = &handle_dropped!(env, cf_arc.get(db));
+                batch.put_cf(cf, key, val)
+            }
+            BatchOperation::Delete(key) => batch.delete(key),
+            BatchOperation::DeleteCf(cf_arc, key) => {
+                let cf = &handle_dropped!(env, cf_arc.get(db));
+                batch.delete_cf(cf, key)
+            }
+            BatchOperation::Merge(key, val) => batch.merge(key, val),
+            BatchOperation::MergeCf(cf_arc, key, val) => {
+                let cf = &handle_dropped!(env, cf_arc.get(db));
+                batch.merge_cf(cf, key, val)
+            }
+            BatchOperation::DeleteRange(from, to) => batch.delete_range(from, to),
+            BatchOperation::DeleteRangeCf(cf_arc, from, to) => {
+                let cf = &handle_dropped!(env, cf_arc.get(db));
+                batch.delete_range_cf(cf, from, to)
+            }
         }
     }
 
-    handle_error!(env, db.write(batch));
-    Ok(atoms::ok().encode(env))
+    write_batch(env, db, batch, args)
 }
 
+// Every NIF which may touch the disk, or which does work proportional to the size of the
+// database, runs on a dirty IO scheduler so that it can't block the normal schedulers
 rustler_export_nifs!(
     "Elixir.Rox.Native",
     [
-        ("open", 3, open),
+        ("open", 3, open, DirtyIo),
+        ("close", 1, close, DirtyIo),
         ("create_snapshot", 1, create_snapshot),
-        ("create_cf", 3, create_cf),
+        ("create_cache", 2, create_cache),
+        (
+            "create_write_buffer_manager",
+            3,
+            create_write_buffer_manager
+        ),
+        ("create_cf", 3, create_cf, DirtyIo),
+        ("drop_cf", 2, drop_cf, DirtyIo),
         ("cf_handle", 2, cf_handle),
-        ("put", 4, put),
-        ("put_cf", 5, put_cf),
-        ("delete", 3, delete),
-        ("delete_cf", 4, delete_cf),
-        ("count", 1, count),
-        ("count_cf", 2, count_cf),
-        ("list_cf", 2, list_cf),
-        ("iterate", 2, iterate),
-        ("iterate_cf", 3, iterate_cf),
-        ("iterator_next", 1, iterator_next),
-        ("iterator_reset", 2, iterator_reset),
-        ("get", 3, get),
-        ("get_cf", 4, get_cf),
-        ("batch_write", 2, batch_write),
+        ("put", 4, put, DirtyIo),
+        ("put_cf", 5, put_cf, DirtyIo),
+        ("delete", 3, delete, DirtyIo),
+        ("delete_cf", 4, delete_cf, DirtyIo),
+        ("delete_range", 4, delete_range, DirtyIo),
+        ("delete_range_cf", 5, delete_range_cf, DirtyIo),
+        ("merge", 4, merge, DirtyIo),
+        ("merge_cf", 5, merge_cf, DirtyIo),
+        ("count", 1, count, DirtyIo),
+        ("count_cf", 2, count_cf, DirtyIo),
+        ("count_exact", 2, count_exact, DirtyIo),
+        ("count_exact_cf", 3, count_exact_cf, DirtyIo),
+        ("list_cf", 2, list_cf, DirtyIo),
+        ("compact_range", 5, compact_range, DirtyIo),
+        ("compact_range_cf", 6, compact_range_cf, DirtyIo),
+        ("get_property", 2, get_property, DirtyIo),
+        ("get_property_cf", 3, get_property_cf, DirtyIo),
+        ("get_int_property", 2, get_int_property, DirtyIo),
+        ("get_int_property_cf", 3, get_int_property_cf, DirtyIo),
+        ("iterate", 3, iterate, DirtyIo),
+        ("iterate_cf", 4, iterate_cf, DirtyIo),
+        ("iterator_next", 1, iterator_next, DirtyIo),
+        ("iterator_next_n", 2, iterator_next_n, DirtyIo),
+        ("iterator_reset", 2, iterator_reset, DirtyIo),
+        ("get", 3, get, DirtyIo),
+        ("get_cf", 4, get_cf, DirtyIo),
+        ("multi_get", 3, multi_get, DirtyIo),
+        ("multi_get_cf", 3, multi_get_cf, DirtyIo),
+        ("exists", 3, exists, DirtyIo),
+        ("exists_cf", 4, exists_cf, DirtyIo),
+        ("batch_write", 3, batch_write, DirtyIo),
     ],
     Some(on_load)
 );
@@ -987,5 +2814,7 @@ fn on_load<'a>(env: Env<'a>, _load_info: Term<'a>) -> bool {
     resource_struct_init!(IteratorHandle, env);
     resource_struct_init!(SnapshotWrapper, env);
     resource_struct_init!(SnapshotHandle, env);
+    resource_struct_init!(CacheHandle, env);
+    resource_struct_init!(WriteBufferManagerHandle, env);
     true
 }
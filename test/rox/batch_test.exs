@@ -36,4 +36,12 @@ defmodule Rox.BatchTest do
     end
   end
 
+  describe "merge/3" do
+    test "schedules an encoded merge operation" do
+      assert Batch.new |> Batch.merge("counter", 1) == %Batch{
+        operations: [merge: {"counter", Rox.Utils.encode(1)}]
+      }
+    end
+  end
+
 end
@@ -48,6 +48,22 @@ defmodule RoxTest do
       assert length(items) > 10
     end
 
+    test "stream with a custom chunk size", %{db: db} do
+      Enum.each(0..9, & :ok = Rox.put(db, "chunk_#{&1}", &1))
+
+      for chunk_size <- [1, 3, 10, 50] do
+        assert Enum.to_list(0..9) ==
+          Rox.stream(db, {:range, "chunk_", "chunk_:"}, chunk_size: chunk_size)
+          |> Enum.map(&elem(&1, 1))
+      end
+    end
+
+    test "stream rejects invalid chunk sizes", %{db: db} do
+      for chunk_size <- [0, -1, 1.5, :all] do
+        assert_raise ArgumentError, fn -> Rox.stream(db, :start, chunk_size: chunk_size) end
+      end
+    end
+
     test "stream_keys", %{db: db} do
       Enum.each(0..9, & :ok = Rox.put(db, to_string(&1), &1))
 
@@ -58,6 +74,60 @@ defmodule RoxTest do
       assert ~w(0 1 2 3 4 5 6 7 8 9) == items
     end
 
+    test "stream a bounded range", %{db: db} do
+      Enum.each(0..9, & :ok = Rox.put(db, "range_#{&1}", &1))
+
+      assert [2, 3, 4] ==
+        Rox.stream(db, {:range, "range_2", "range_5"})
+        |> Enum.map(&elem(&1, 1))
+
+      assert [3, 4, 5] ==
+        Rox.stream(db, {:range, {:exclusive, "range_2"}, {:inclusive, "range_5"}})
+        |> Enum.map(&elem(&1, 1))
+    end
+
+    test "count and count_exact", %{db: db} do
+      Enum.each(0..9, & :ok = Rox.put(db, "count_#{&1}", &1))
+
+      assert is_integer(Rox.count(db))
+      assert Rox.count_exact(db) >= 10
+      assert 10 == Rox.count_exact(db, {:prefix, "count_"})
+      assert 3 == Rox.count_exact(db, {:range, "count_2", "count_5"})
+    end
+
+    test "Enum.count is exact", %{db: db, people: people} do
+      Enum.each(0..9, & :ok = Rox.put(people, "enum_count_#{&1}", &1))
+
+      assert Rox.count_exact(db) == Enum.count(db)
+      assert Rox.count_exact(people) == Enum.count(people)
+      assert Enum.count(Rox.stream(people)) == Enum.count(people)
+    end
+
+    test "concurrent writers", %{db: db} do
+      1..8
+      |> Task.async_stream(fn worker ->
+        Enum.each(0..99, & :ok = Rox.put(db, "concurrent_#{worker}_#{&1}", &1))
+      end)
+      |> Stream.run
+
+      assert 800 == Rox.count_exact(db, {:prefix, "concurrent_"})
+    end
+
+    test "count_exact counts past the chunk size", %{db: db, people: people} do
+      0..24_999
+      |> Enum.reduce(Batch.new, &Batch.put(&2, people, "chunked_#{&1}", &1))
+      |> Batch.write(db)
+
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      :ok = Rox.put(people, "chunked_25000", 25_000)
+
+      assert 25_001 == Rox.count_exact(people, {:prefix, "chunked_"})
+      assert 11_111 == Rox.count_exact(people, {:range, "chunked_1", "chunked_2"})
+
+      {:ok, snapshot_people} = Rox.cf_handle(snapshot, "people")
+      assert 25_000 == Rox.count_exact(snapshot_people, {:prefix, "chunked_"})
+    end
+
     test "delete", %{db: db} do
       assert :not_found = Rox.get(db, "delete_test")
       assert :ok = Rox.put(db, "delete_test", "some_val")
@@ -68,6 +138,275 @@ defmodule RoxTest do
     end
   end
 
+  describe "Multi get" do
+    test "looks up keys in order", %{db: db, people: people} do
+      :ok = Rox.put(db, "multi_get_1", "one")
+      :ok = Rox.put(db, "multi_get_2", %{two: 2})
+      :ok = Rox.put(people, "multi_get_1", "person")
+
+      assert [{:ok, %{two: 2}}, :not_found, {:ok, "one"}] =
+        Rox.multi_get(db, ["multi_get_2", "multi_get_missing", "multi_get_1"])
+
+      assert [{:ok, "person"}, :not_found] = Rox.multi_get(people, ["multi_get_1", "multi_get_2"])
+      assert [] = Rox.multi_get(db, [])
+    end
+
+    test "looks up keys across column families", %{db: db, people: people} do
+      :ok = Rox.put(db, "multi_get_cf", "default")
+      :ok = Rox.put(people, "multi_get_cf", "person")
+
+      {:ok, default} = Rox.cf_handle(db, "default")
+
+      assert [{:ok, "person"}, {:ok, "default"}, :not_found] =
+        Rox.multi_get(db, [{people, "multi_get_cf"}, {default, "multi_get_cf"}, {people, "multi_get_missing"}])
+    end
+
+    test "looks up plain keys in the default column family", %{db: db, people: people} do
+      :ok = Rox.put(db, "multi_get_mixed", "default")
+      :ok = Rox.put(people, "multi_get_mixed", "person")
+
+      assert [{:ok, "default"}, {:ok, "person"}, :not_found] =
+        Rox.multi_get(db, ["multi_get_mixed", {people, "multi_get_mixed"}, "multi_get_missing"])
+
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      {:ok, snapshot_people} = Rox.cf_handle(snapshot, "people")
+
+      assert [{:ok, "person"}, {:ok, "default"}] =
+        Rox.multi_get(snapshot, [{snapshot_people, "multi_get_mixed"}, "multi_get_mixed"])
+    end
+
+    test "looks up keys in snapshots", %{db: db, people: people} do
+      :ok = Rox.put(db, "multi_get_snapshot", "before")
+      :ok = Rox.put(people, "multi_get_snapshot", "before")
+
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      {:ok, snapshot_people} = Rox.cf_handle(snapshot, "people")
+
+      :ok = Rox.put(db, "multi_get_snapshot", "after")
+      :ok = Rox.put(people, "multi_get_snapshot", "after")
+
+      assert [{:ok, "before"}] = Rox.multi_get(snapshot, ["multi_get_snapshot"])
+      assert [{:ok, "before"}] = Rox.multi_get(snapshot_people, ["multi_get_snapshot"])
+      assert [{:ok, "before"}] = Rox.multi_get(snapshot, [{snapshot_people, "multi_get_snapshot"}])
+    end
+  end
+
+  describe "Existence checks" do
+    setup :tmp_db_path
+
+    test "checks keys in databases and column families", %{db: db, people: people} do
+      :ok = Rox.put(db, "exists", "value")
+      :ok = Rox.put(people, "exists_person", %{name: "Bob"})
+
+      assert true == Rox.exists?(db, "exists")
+      assert false == Rox.exists?(db, "exists_missing")
+      assert true == Rox.exists?(people, "exists_person")
+      assert false == Rox.exists?(people, "exists")
+
+      :ok = Rox.delete(db, "exists")
+      assert false == Rox.exists?(db, "exists")
+    end
+
+    test "returns errors when the check fails", %{db: db, path: path} do
+      assert {:error, {:invalid_option, :fill_cash, :unknown_option}} =
+        Rox.exists?(db, "exists", fill_cash: false)
+
+      {:ok, closed} = Rox.open(path, create_if_missing: true)
+      :ok = Rox.close(closed)
+
+      assert {:error, :closed} = Rox.exists?(closed, "exists")
+    end
+
+    test "checks keys in snapshots", %{db: db, people: people} do
+      :ok = Rox.put(db, "exists_snapshot", "value")
+      :ok = Rox.put(people, "exists_snapshot", "value")
+
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      {:ok, snapshot_people} = Rox.cf_handle(snapshot, "people")
+
+      :ok = Rox.delete(db, "exists_snapshot")
+      :ok = Rox.delete(people, "exists_snapshot")
+      :ok = Rox.put(db, "exists_after_snapshot", "value")
+
+      assert Rox.exists?(snapshot, "exists_snapshot")
+      assert Rox.exists?(snapshot_people, "exists_snapshot")
+      refute Rox.exists?(snapshot, "exists_after_snapshot")
+      refute Rox.exists?(db, "exists_snapshot")
+    end
+  end
+
+  describe "Read options" do
+    test "get honors read options", %{db: db, people: people} do
+      assert :ok = Rox.put(db, "read_opts_test", "val")
+      assert :ok = Rox.put(people, "read_opts_test", "val")
+
+      assert {:ok, "val"} = Rox.get(db, "read_opts_test", fill_cache: false, verify_checksums: true)
+      assert {:ok, "val"} = Rox.get(people, "read_opts_test", fill_cache: false)
+    end
+
+    test "stream honors iterate bounds", %{db: db} do
+      Enum.each(0..9, & :ok = Rox.put(db, "bounds_#{&1}", &1))
+
+      forward =
+        Rox.stream_keys(db, {:from, "bounds_2", :forward}, iterate_upper_bound: "bounds_5")
+        |> Enum.to_list
+
+      assert ~w(bounds_2 bounds_3 bounds_4) == forward
+
+      reverse =
+        Rox.stream_keys(db, {:from, "bounds_4", :reverse}, iterate_lower_bound: "bounds_2")
+        |> Enum.to_list
+
+      assert ~w(bounds_4 bounds_3 bounds_2) == reverse
+    end
+  end
+
+  describe "Prefix scans" do
+    setup :tmp_db_path
+
+    test "stream_prefix only returns keys with the prefix", %{db: db} do
+      Enum.each(~w(tenant_a tenant_b), fn tenant ->
+        Enum.each(0..4, & :ok = Rox.put(db, "#{tenant}:#{&1}", &1))
+      end)
+
+      assert ~w(tenant_a:0 tenant_a:1 tenant_a:2 tenant_a:3 tenant_a:4) ==
+        Rox.stream_prefix(db, "tenant_a:")
+        |> Enum.map(&elem(&1, 0))
+    end
+
+    test "stream_prefix with a prefix extractor", %{path: path} do
+      {:ok, db} =
+        Rox.open(path, create_if_missing: true, prefix_extractor: {:fixed, 4}, memtable_prefix_bloom_ratio: 0.1)
+
+      Enum.each(~w(aaaa:1 aaaa:2 bbbb:1), & :ok = Rox.put(db, &1, &1))
+
+      assert ~w(aaaa:1 aaaa:2) ==
+        Rox.stream_prefix(db, "aaaa")
+        |> Enum.map(&elem(&1, 0))
+
+      assert ~w(aaaa:1 aaaa:2) ==
+        Rox.stream_prefix(db, "aaaa", prefix_same_as_start: true)
+        |> Enum.map(&elem(&1, 0))
+    end
+
+    test "stream_prefix with a prefix shorter than the prefix extractor", %{path: path} do
+      {:ok, db} =
+        Rox.open(path, create_if_missing: true, prefix_extractor: {:fixed, 4}, memtable_prefix_bloom_ratio: 0.1)
+
+      Enum.each(~w(aaaa:1 aabb:1 aacc:2 abcd:1 bbbb:1), & :ok = Rox.put(db, &1, &1))
+
+      assert ~w(aaaa:1 aabb:1 aacc:2) ==
+        Rox.stream_prefix(db, "aa")
+        |> Enum.map(&elem(&1, 0))
+    end
+
+    test "stream_prefix with a capped prefix extractor", %{path: path} do
+      opts =
+        [create_if_missing: true, prefix_extractor: {:capped, 4}, memtable_prefix_bloom_ratio: 0.1]
+
+      {:ok, db} = Rox.open(path, opts)
+
+      Enum.each(~w(aa aab aaaa:1 aaaa:2 aaab bbbb:1), & :ok = Rox.put(db, &1, &1))
+
+      assert_capped_prefixes = fn db ->
+        assert ~w(aaaa:1 aaaa:2) ==
+          Rox.stream_prefix(db, "aaaa")
+          |> Enum.map(&elem(&1, 0))
+
+        assert ~w(aab) ==
+          Rox.stream_prefix(db, "aab")
+          |> Enum.map(&elem(&1, 0))
+
+        assert ~w(aa aaaa:1 aaaa:2 aaab aab) ==
+          Rox.stream_prefix(db, "aa")
+          |> Enum.map(&elem(&1, 0))
+      end
+
+      assert_capped_prefixes.(db)
+
+      assert :ok = Rox.close(db)
+      assert {:ok, reopened} = Rox.open(path, opts)
+
+      assert_capped_prefixes.(reopened)
+    end
+  end
+
+  describe "Properties" do
+    test "get_property", %{db: db, people: people} do
+      assert {:ok, stats} = Rox.get_property(db, "rocksdb.stats")
+      assert is_binary(stats)
+
+      assert {:ok, _} = Rox.get_property(people, "rocksdb.num-files-at-level0")
+      assert :not_found = Rox.get_property(db, "rocksdb.not-a-property")
+    end
+
+    test "get_int_property", %{db: db, people: people} do
+      assert {:ok, size} = Rox.get_int_property(db, "rocksdb.cur-size-all-mem-tables")
+      assert is_integer(size)
+
+      assert {:ok, _} = Rox.get_int_property(people, "rocksdb.estimate-num-keys")
+      assert :not_found = Rox.get_int_property(db, "rocksdb.stats")
+    end
+  end
+
+  describe "Range deletes" do
+    test "delete_range", %{db: db, people: people} do
+      Enum.each(0..9, & :ok = Rox.put(db, "purge_#{&1}", &1))
+      Enum.each(0..9, & :ok = Rox.put(people, "purge_#{&1}", &1))
+
+      assert :ok = Rox.delete_range(db, "purge_2", "purge_8")
+      assert :ok = Rox.delete_range(people, "purge_0", "purge_:", sync: true)
+
+      assert ~w(purge_0 purge_1 purge_8 purge_9) ==
+        Rox.stream_prefix(db, "purge_") |> Enum.map(&elem(&1, 0))
+      assert 0 == Rox.count_exact(people, {:prefix, "purge_"})
+    end
+
+    test "delete_range in a batch", %{db: db, people: people} do
+      Enum.each(0..9, & :ok = Rox.put(db, "batch_purge_#{&1}", &1))
+      Enum.each(0..9, & :ok = Rox.put(people, "batch_purge_#{&1}", &1))
+
+      assert :ok =
+        Batch.new
+        |> Batch.delete_range("batch_purge_0", "batch_purge_5")
+        |> Batch.delete_range(people, "batch_purge_5", "batch_purge_:")
+        |> Batch.write(db)
+
+      assert 5 == Rox.count_exact(db, {:prefix, "batch_purge_"})
+      assert 5 == Rox.count_exact(people, {:prefix, "batch_purge_"})
+    end
+  end
+
+  describe "Manual compactions" do
+    setup :tmp_db_path
+
+    test "compact_range", %{db: db, people: people} do
+      assert :ok = Rox.compact_range(db)
+      assert :ok = Rox.compact_range(people, "purge_", "purge_:", bottommost_level_compaction: :force)
+
+      assert {:ok, ref} = Rox.compact_range(db, nil, "purge_:", notify: true)
+      assert_receive {:rox_compaction_complete, ^ref}, 5_000
+    end
+
+    test "background compactions don't hold up other operations", %{path: path} do
+      {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["cf"])
+
+      Enum.each(0..999, & :ok = Rox.put(cf, "compact_#{&1}", &1))
+
+      assert {:ok, ref} = Rox.compact_range(cf, nil, nil, notify: true)
+
+      assert {:ok, _} = Rox.create_cf(db, "created")
+      assert :ok = Rox.drop_cf(db, "created")
+      assert :ok = Rox.close(db)
+      assert_receive {:rox_compaction_complete, ^ref}, 5_000
+
+      assert {:ok, reopened, %{"cf" => cf}} = Rox.open(path, [], ["cf"])
+      assert 1000 == Rox.count_exact(cf)
+      assert :ok = Rox.close(reopened)
+    end
+  end
+
   describe "Working with non-default column family" do
     test "simple put and get", %{people: people} do
       assert :not_found = Rox.get(people, "put_test")
@@ -110,6 +449,12 @@ defmodule RoxTest do
       {:ok, %{snapshot: snapshot}}
     end
 
+    test "snapshots can be counted", %{snapshot: snapshot, db: db} do
+      assert 9 == Rox.count_exact(snapshot, {:prefix, "zz"})
+      assert :ok = Rox.put(db, "zz_after_snapshot", "val")
+      assert 9 == Rox.count_exact(snapshot, {:prefix, "zz"})
+    end
+
     test "snapshots can be read from", %{snapshot: snapshot} do
       assert {:ok, "some_val"} == Rox.get(snapshot, "snapshot_read_test")
     end
@@ -148,6 +493,663 @@ defmodule RoxTest do
     end
   end
 
+  describe "Merge operators" do
+    setup :tmp_db_path
+
+    test "int64_add", %{path: path} do
+      {:ok, db, %{"counters" => counters}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true, merge_operator: :int64_add], ["counters"])
+
+      assert :ok = Rox.merge(db, "counter", 1)
+      assert :ok = Rox.merge(db, "counter", 2)
+      assert :ok = Rox.merge(db, "counter", -5)
+      assert {:ok, -2} = Rox.get(db, "counter")
+
+      assert :ok = Rox.merge(counters, "counter", 10)
+      assert {:ok, 10} = Rox.get(counters, "counter")
+    end
+
+    test "append", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true, merge_operator: {:append, ","})
+
+      assert :ok = Rox.put(db, "list", "a")
+      assert :ok = Rox.merge(db, "list", "b")
+      assert :ok = Rox.merge(db, "list", "c")
+      assert {:ok, "a,b,c"} = Rox.get(db, "list")
+    end
+
+    test "term_set_union", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true, merge_operator: :term_set_union)
+
+      assert :ok = Rox.merge(db, "tags", [:b, :a])
+      assert :ok = Rox.merge(db, "tags", [:c, :a])
+      assert {:ok, [:a, :b, :c]} = Rox.get(db, "tags")
+    end
+
+    test "max and min", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true, merge_operator: :max)
+      {:ok, mins} = Rox.create_cf(db, "mins", merge_operator: :min)
+
+      Enum.each([3, 7, 5], & :ok = Rox.merge(db, "extreme", &1))
+      Enum.each([3, 7, 5], & :ok = Rox.merge(mins, "extreme", &1))
+
+      assert {:ok, 7} = Rox.get(db, "extreme")
+      assert {:ok, 3} = Rox.get(mins, "extreme")
+    end
+
+    test "in batches", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true, merge_operator: :int64_add)
+
+      assert :ok =
+        Batch.new
+        |> Batch.merge("batch_counter", 1)
+        |> Batch.merge("batch_counter", 41)
+        |> Batch.write(db)
+
+      assert {:ok, 42} = Rox.get(db, "batch_counter")
+    end
+  end
+
+  describe "Closing" do
+    setup :tmp_db_path
+
+    setup %{path: path} do
+      {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["cf"])
+
+      {:ok, %{db: db, cf: cf}}
+    end
+
+    test "a closed database can be reopened", %{path: path, db: db} do
+      assert :ok = Rox.put(db, "key", "val")
+      assert :ok = Rox.close(db)
+      assert :ok = Rox.close(db)
+
+      assert {:ok, reopened} = Rox.open(path)
+      assert {:ok, "val"} = Rox.get(reopened, "key")
+    end
+
+    test "derived handles return errors once closed", %{db: db, cf: cf} do
+      Enum.each(0..9, & :ok = Rox.put(db, "key_#{&1}", &1))
+
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      cursor = Rox.stream(db)
+
+      assert :ok = Rox.close(db)
+
+      assert {:error, :closed} = Rox.get(db, "key_1")
+      assert {:error, :closed} = Rox.put(db, "key_1", "val")
+      assert {:error, :closed} = Rox.get(cf, "key_1")
+      assert {:error, :closed} = Rox.put(cf, "key_1", "val")
+      assert {:error, :closed} = Rox.get(snapshot, "key_1")
+      assert {:error, :closed} = Rox.exists?(db, "key_1")
+      assert {:error, :closed} = Rox.exists?(cf, "key_1")
+      assert {:error, :closed} = Rox.exists?(snapshot, "key_1")
+      assert {:error, :closed} = Rox.stream(db)
+      assert {:error, :closed} = Rox.create_snapshot(db)
+      assert {:error, :closed} = Rox.compact_range(db)
+      assert {:error, :closed} = Rox.compact_range(cf, nil, nil, notify: true)
+
+      assert_raise ArgumentError, fn -> Enum.to_list(cursor) end
+    end
+  end
+
+  describe "Write options" do
+    setup :tmp_db_path
+
+    setup %{path: path} do
+      {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true, merge_operator: :int64_add], ["cf"])
+
+      {:ok, %{db: db, cf: cf}}
+    end
+
+    for opts <- [[sync: true], [disable_wal: true], [no_slowdown: true], [low_pri: true]] do
+      test "every write accepts #{inspect opts}", %{db: db, cf: cf} do
+        opts = unquote(opts)
+
+        for db_or_cf <- [db, cf] do
+          assert :ok = Rox.put(db_or_cf, "put", "val", opts)
+          assert {:ok, "val"} = Rox.get(db_or_cf, "put")
+
+          assert :ok = Rox.merge(db_or_cf, "merge", 1, opts)
+          assert {:ok, 1} = Rox.get(db_or_cf, "merge")
+
+          assert :ok = Rox.delete(db_or_cf, "put", opts)
+          assert :not_found = Rox.get(db_or_cf, "put")
+
+          assert :ok = Rox.delete_range(db_or_cf, "a", "z", opts)
+          assert :not_found = Rox.get(db_or_cf, "merge")
+        end
+
+        assert :ok =
+          Batch.new
+          |> Batch.put("batch", "val")
+          |> Batch.put(cf, "batch", "val")
+          |> Batch.write(db, opts)
+
+        assert {:ok, "val"} = Rox.get(db, "batch")
+        assert {:ok, "val"} = Rox.get(cf, "batch")
+      end
+    end
+
+    test "every write rejects invalid options", %{db: db, cf: cf} do
+      for db_or_cf <- [db, cf] do
+        assert {:error, {:invalid_option, :sync, :invalid_value}} = Rox.put(db_or_cf, "key", "val", sync: "yes")
+        assert {:error, {:invalid_option, :sync, :invalid_value}} = Rox.merge(db_or_cf, "key", 1, sync: "yes")
+        assert {:error, {:invalid_option, :sync, :invalid_value}} = Rox.delete(db_or_cf, "key", sync: "yes")
+        assert {:error, {:invalid_option, :sync, :invalid_value}} =
+          Rox.delete_range(db_or_cf, "a", "z", sync: "yes")
+
+        assert :not_found = Rox.get(db_or_cf, "key")
+      end
+    end
+
+    test "write stalls can be queried", %{db: db} do
+      assert {:ok, %{write_stopped: false, delayed_write_rate: 0}} = Rox.write_stall_status(db)
+      assert :ok = Rox.put(db, "key", "val", no_slowdown: true)
+    end
+
+    test "disable_wal skips the write-ahead log", %{path: path, db: db} do
+      wal_size = fn ->
+        Path.join(path, "*.log")
+        |> Path.wildcard
+        |> Enum.map(&File.stat!(&1).size)
+        |> Enum.sum
+      end
+
+      assert :ok = Rox.put(db, "unlogged", String.duplicate("val", 1000), disable_wal: true)
+      assert wal_size.() == 0
+
+      assert :ok = Rox.put(db, "logged", String.duplicate("val", 1000))
+      assert wal_size.() > 0
+    end
+  end
+
+  describe "Write stalls" do
+    setup :tmp_db_path
+
+    test "no_slowdown writes fail once writes are stopped", %{path: path} do
+      # Turning off automatic compactions would also turn off the level 0 write triggers, so
+      # instead nothing is ever picked: universal compactions need more sorted runs than will
+      # be flushed before writes stop and size amplification is never high enough
+      opts = [
+        create_if_missing: true,
+        write_buffer_size: 64 * 1024,
+        compaction_style: :universal,
+        universal_compaction_options: [min_merge_width: 100, max_size_amplification_percent: 100_000],
+        level_zero_file_num_compaction_trigger: 2,
+        level_zero_slowdown_writes_trigger: 2,
+        level_zero_stop_writes_trigger: 2
+      ]
+
+      {:ok, db} = Rox.open(path, opts)
+      assert {:ok, %{write_stopped: false}} = Rox.write_stall_status(db)
+
+      level_zero_files = fn ->
+        {:ok, files} = Rox.get_property(db, "rocksdb.num-files-at-level0")
+        String.to_integer(files)
+      end
+
+      # Fill memtables until enough of them have been flushed to level 0
+      value = String.duplicate("val", 8 * 1024)
+      assert Enum.find(1..1_000, fn i ->
+        _ = Rox.put(db, "key_#{i}", value, no_slowdown: true)
+        Process.sleep(10)
+
+        level_zero_files.() >= 2
+      end)
+
+      assert {:ok, %{write_stopped: true}} = Rox.write_stall_status(db)
+      assert {:error, :incomplete} = Rox.put(db, "stalled", "val", no_slowdown: true)
+      assert {:error, :incomplete} =
+        Batch.new
+        |> Batch.put("stalled", "val")
+        |> Batch.write(db, no_slowdown: true)
+
+      assert :not_found = Rox.get(db, "stalled")
+    end
+  end
+
+  describe "Option validation" do
+    setup :tmp_db_path
+
+    test "rejects unknown database options", %{path: path} do
+      assert {:error, {:invalid_option, :write_buffer_sise, :unknown_option}} =
+        Rox.open(path, create_if_missing: true, write_buffer_sise: 1024)
+
+      assert {:error, {:invalid_option, :block_sise, :unknown_option}} =
+        Rox.open(path, create_if_missing: true, block_based_table_options: [block_sise: 1024])
+    end
+
+    test "rejects database options of the wrong type", %{path: path} do
+      assert {:error, {:invalid_option, :create_if_missing, :invalid_value}} =
+        Rox.open(path, create_if_missing: "yes")
+
+      assert {:error, {:invalid_option, :compaction_style, :invalid_value}} =
+        Rox.open(path, create_if_missing: true, compaction_style: :sideways)
+
+      assert {:error, {:invalid_option, :fifo_compaction_options, :invalid_value}} =
+        Rox.open(path, create_if_missing: true, fifo_compaction_options: 1024)
+    end
+
+    test "rejects conflicting compaction options", %{path: path} do
+      assert {:error, {:invalid_option, :disable_auto_compactions, :invalid_value}} =
+        Rox.open(path, create_if_missing: true, compaction_style: :none, disable_auto_compactions: false)
+
+      assert {:ok, db} =
+        Rox.open(path, create_if_missing: true, compaction_style: :none, disable_auto_compactions: true)
+
+      assert {:error, {:invalid_option, :disable_auto_compactions, :invalid_value}} =
+        Rox.create_cf(db, "cf", compaction_style: :none, disable_auto_compactions: false)
+    end
+
+    test "rejects unknown column family options", %{path: path} do
+      assert {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["cf"])
+
+      assert {:error, {:invalid_option, :max_opne_files, :unknown_option}} =
+        Rox.create_cf(db, "other", max_opne_files: 10)
+
+      assert :ok = Rox.put(cf, "key", "val")
+    end
+
+    test "rejects database wide options for column families", %{path: path} do
+      assert {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true, max_open_files: 64], ["cf"])
+
+      assert :ok = Rox.put(cf, "key", "val")
+
+      assert {:error, {:invalid_option, :create_if_missing, :unknown_option}} =
+        Rox.create_cf(db, "other", create_if_missing: true)
+
+      assert {:error, {:invalid_option, :write_buffer_manager, :unknown_option}} =
+        Rox.create_cf(db, "other", write_buffer_manager: nil)
+
+      assert :ok = Rox.close(db)
+
+      assert {:error, {:invalid_option, :max_open_files, :unknown_option}} =
+        Rox.open(path, [], [{"cf", [max_open_files: 64]}])
+    end
+
+    test "rejects invalid write options", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true)
+
+      assert {:error, {:invalid_option, :synk, :unknown_option}} = Rox.delete(db, "key", synk: true)
+      assert {:error, {:invalid_option, :sync, :invalid_value}} = Rox.delete(db, "key", sync: "yes")
+    end
+
+    test "rejects invalid read options", %{path: path} do
+      {:ok, db, %{"cf" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["cf"])
+
+      assert {:error, {:invalid_option, :fill_cash, :unknown_option}} = Rox.get(db, "key", fill_cash: false)
+      assert {:error, {:invalid_option, :fill_cache, :invalid_value}} = Rox.get(cf, "key", fill_cache: "no")
+      assert {:error, {:invalid_option, :read_tier, :invalid_value}} = Rox.stream(db, :start, read_tier: :disk)
+      assert {:error, {:invalid_option, :fill_cash, :unknown_option}} = Rox.multi_get(db, ["key"], fill_cash: false)
+    end
+
+    test "rejects invalid compact options", %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true)
+
+      assert {:error, {:invalid_option, :target_levle, :unknown_option}} =
+        Rox.compact_range(db, nil, nil, target_levle: 1)
+
+      assert {:error, {:invalid_option, :bottommost_level_compaction, :invalid_value}} =
+        Rox.compact_range(db, nil, nil, bottommost_level_compaction: :always)
+    end
+  end
+
+  describe "Column family descriptors" do
+    setup :tmp_db_path
+
+    test "opens each column family with its own options", %{path: path} do
+      descriptors = [
+        {"counters", [merge_operator: :int64_add]},
+        {"logs", [merge_operator: {:append, ","}, write_buffer_size: 1_048_576]},
+        "plain"
+      ]
+
+      {:ok, db, %{"counters" => counters, "logs" => logs, "plain" => plain}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], descriptors)
+
+      :ok = Rox.merge(counters, "hits", 1)
+      :ok = Rox.merge(logs, "line", "a")
+      :ok = Rox.put(plain, "key", "val")
+      :ok = Rox.close(db)
+
+      {:ok, _db, %{"counters" => counters, "logs" => logs, "plain" => plain}} =
+        Rox.open(path, [], descriptors)
+
+      :ok = Rox.merge(counters, "hits", 2)
+      :ok = Rox.merge(logs, "line", "b")
+
+      assert {:ok, 3} = Rox.get(counters, "hits")
+      assert {:ok, "a,b"} = Rox.get(logs, "line")
+      assert {:ok, "val"} = Rox.get(plain, "key")
+    end
+
+    test "only creates the column families which are missing", %{path: path} do
+      {:ok, db, %{"existing" => existing}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["existing"])
+
+      :ok = Rox.put(existing, "key", "val")
+      :ok = Rox.close(db)
+
+      {:ok, _db, %{"existing" => existing, "added" => added}} =
+        Rox.open(path, [auto_create_column_families: true], ["existing", {"added", [merge_operator: :int64_add]}])
+
+      :ok = Rox.merge(added, "hits", 1)
+
+      assert {:ok, "val"} = Rox.get(existing, "key")
+      assert {:ok, 1} = Rox.get(added, "hits")
+    end
+  end
+
+  describe "Block based table options" do
+    setup :tmp_db_path
+
+    test "apply to databases and column families", %{path: path} do
+      table_opts = [
+        block_size: 16 * 1024,
+        block_cache_size: 8 * 1024 * 1024,
+        bloom_filter_policy: 10,
+        cache_index_and_filter_blocks: true,
+        format_version: 2
+      ]
+
+      {:ok, db} = Rox.open(path, create_if_missing: true, block_based_table_options: table_opts)
+      {:ok, lookups} =
+        Rox.create_cf(db, "lookups", block_based_table_options: [no_block_cache: true, bloom_filter_policy: 10])
+
+      Enum.each(0..99, fn i ->
+        :ok = Rox.put(db, "key_#{i}", i)
+        :ok = Rox.put(lookups, "key_#{i}", i)
+      end)
+
+      assert :ok = Rox.compact_range(db)
+      assert :ok = Rox.compact_range(lookups)
+
+      assert {:ok, 42} = Rox.get(db, "key_42")
+      assert {:ok, 42} = Rox.get(lookups, "key_42")
+      assert :not_found = Rox.get(lookups, "missing")
+
+      assert {:ok, 8_388_608} = Rox.get_int_property(db, "rocksdb.block-cache-capacity")
+      assert :not_found = Rox.get_int_property(lookups, "rocksdb.block-cache-capacity")
+
+      {:ok, plain} = Rox.create_cf(db, "plain")
+      :ok = Rox.put(plain, "key", "val")
+      assert :ok = Rox.compact_range(plain)
+
+      filter_size = fn db_or_cf ->
+        {:ok, props} = Rox.get_property(db_or_cf, "rocksdb.aggregated-table-properties")
+        [_, size] = Regex.run(~r/filter block size=(\d+)/, props)
+        String.to_integer(size)
+      end
+
+      assert filter_size.(db) > 0
+      assert filter_size.(lookups) > 0
+      assert filter_size.(plain) == 0
+    end
+
+    test "column families inheriting the database's options share its block cache", %{path: path} do
+      opts = [
+        create_if_missing: true,
+        auto_create_column_families: true,
+        block_based_table_options: [block_cache_size: 8 * 1024 * 1024]
+      ]
+
+      {:ok, db, %{"small" => small, "large" => large}} = Rox.open(path, opts, ["small", "large"])
+
+      Enum.each(0..9, & :ok = Rox.put(small, "key_#{&1}", &1))
+      Enum.each(0..999, & :ok = Rox.put(large, "key_#{&1}", &1))
+
+      for cf <- [small, large] do
+        :ok = Rox.compact_range(cf)
+        {:ok, 7} = Rox.get(cf, "key_7")
+      end
+
+      # Separate caches would only hold the blocks of their own column family
+      assert [usage] =
+        [db, small, large]
+        |> Enum.map(fn db_or_cf ->
+          {:ok, usage} = Rox.get_int_property(db_or_cf, "rocksdb.block-cache-usage")
+          usage
+        end)
+        |> Enum.uniq
+
+      assert usage > 0
+      assert {:ok, 8_388_608} = Rox.get_int_property(large, "rocksdb.block-cache-capacity")
+    end
+  end
+
+  describe "Compaction styles" do
+    setup :tmp_db_path
+
+    setup %{path: path} do
+      {:ok, db} = Rox.open(path, create_if_missing: true)
+
+      {:ok, %{db: db}}
+    end
+
+    test "universal", %{db: db, path: path} do
+      universal_opts = [
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: 8,
+        max_size_amplification_percent: 200
+      ]
+
+      {:ok, cf} =
+        Rox.create_cf(db, "universal", compaction_style: :universal, universal_compaction_options: universal_opts)
+
+      :ok = Rox.put(cf, "key", "val")
+      assert :ok = Rox.compact_range(cf)
+      assert {:ok, "val"} = Rox.get(cf, "key")
+
+      options = persisted_cf_options(path, "universal")
+      assert options =~ "compaction_style=kCompactionStyleUniversal"
+      assert options =~ ~r/\bsize_ratio=1\b/
+      assert options =~ ~r/\bmin_merge_width=2\b/
+      assert options =~ ~r/\bmax_merge_width=8\b/
+      assert options =~ ~r/\bmax_size_amplification_percent=200\b/
+    end
+
+    test "fifo", %{db: db, path: path} do
+      fifo_opts = [max_table_files_size: 64 * 1024 * 1024, ttl: 60 * 60]
+
+      {:ok, cf} =
+        Rox.create_cf(db, "events", compaction_style: :fifo, fifo_compaction_options: fifo_opts)
+
+      :ok = Rox.put(cf, "key", "val")
+      assert {:ok, "val"} = Rox.get(cf, "key")
+
+      options = persisted_cf_options(path, "events")
+      assert options =~ "compaction_style=kCompactionStyleFIFO"
+      assert options =~ ~r/\bmax_table_files_size=67108864\b/
+      assert options =~ ~r/^  ttl=3600$/m
+    end
+
+    test "none", %{db: db, path: path} do
+      {:ok, cf} = Rox.create_cf(db, "manual", compaction_style: :none)
+
+      :ok = Rox.put(cf, "key", "val")
+      assert :ok = Rox.compact_range(cf)
+      assert {:ok, "val"} = Rox.get(cf, "key")
+
+      options = persisted_cf_options(path, "manual")
+      assert options =~ ~r/^  disable_auto_compactions=true$/m
+      assert options =~ ~r/^  compaction_style=kCompactionStyleLevel$/m
+    end
+  end
+
+  describe "Compression" do
+    setup :tmp_db_path
+
+    test "zstd with per level and bottommost compression", %{path: path} do
+      opts = [
+        create_if_missing: true,
+        num_levels: 4,
+        compression_type: :zstd,
+        compression_options: [level: 3],
+        compression_per_level: [:none, :lz4, :lz4, :zstd],
+        bottommost_compression_type: :zstd,
+        bottommost_compression_options: [level: 19, max_dict_bytes: 16 * 1024]
+      ]
+
+      {:ok, db} = Rox.open(path, opts)
+
+      Enum.each(0..99, & :ok = Rox.put(db, "key_#{&1}", String.duplicate("val", &1)))
+      assert :ok = Rox.compact_range(db)
+
+      assert {:ok, "valvalval"} = Rox.get(db, "key_3")
+
+      options = persisted_cf_options(path, "default")
+      assert options =~ ~r/^  compression=kZSTD$/m
+      assert options =~ ~r/^  compression_per_level=kNoCompression:kLZ4Compression:kLZ4Compression:kZSTD$/m
+      assert options =~ ~r/^  bottommost_compression=kZSTD$/m
+      assert options =~ ~r/^  compression_opts=\{[^}]*\blevel=3\b/m
+      assert options =~ ~r/^  bottommost_compression_opts=\{[^}]*\blevel=19\b/m
+      assert options =~ ~r/^  bottommost_compression_opts=\{[^}]*\bmax_dict_bytes=16384\b/m
+    end
+  end
+
+  describe "Shared memory budgets" do
+    setup %{test: test} do
+      paths = Enum.map(1..2, &Path.join(__DIR__, "#{test}_#{&1}.rocksdb"))
+      on_exit fn -> Enum.each(paths, &File.rm_rf/1) end
+
+      {:ok, %{paths: paths}}
+    end
+
+    test "caches and write buffer managers can be shared between databases", %{paths: paths} do
+      assert {:ok, %Rox.Cache{} = cache} = Rox.Cache.new(8 * 1024 * 1024)
+      assert {:ok, %Rox.Cache{}} = Rox.Cache.new(8 * 1024 * 1024, :clock)
+      assert {:ok, %Rox.WriteBufferManager{} = manager} =
+        Rox.WriteBufferManager.new(4 * 1024 * 1024, cache: cache)
+
+      opts = [
+        create_if_missing: true,
+        block_based_table_options: [block_cache: cache],
+        write_buffer_manager: manager
+      ]
+
+      dbs = Enum.map(paths, fn path ->
+        {:ok, db} = Rox.open(path, opts)
+        {:ok, cf} = Rox.create_cf(db, "cf", block_based_table_options: [block_cache: cache])
+
+        :ok = Rox.put(db, "key", path)
+        :ok = Rox.put(cf, "key", path)
+        :ok = Rox.compact_range(db)
+
+        {db, cf}
+      end)
+
+      for {{db, cf}, path} <- Enum.zip(dbs, paths) do
+        assert {:ok, ^path} = Rox.get(db, "key")
+        assert {:ok, ^path} = Rox.get(cf, "key")
+      end
+
+      handles = Enum.flat_map(dbs, &Tuple.to_list/1)
+
+      for db_or_cf <- handles do
+        assert {:ok, 8_388_608} = Rox.get_int_property(db_or_cf, "rocksdb.block-cache-capacity")
+      end
+
+      # Every handle reports the usage of the same cache, which is charged for all memtables
+      assert [usage] =
+        handles
+        |> Enum.map(fn db_or_cf ->
+          {:ok, usage} = Rox.get_int_property(db_or_cf, "rocksdb.block-cache-usage")
+          usage
+        end)
+        |> Enum.uniq
+
+      assert usage > 0
+    end
+  end
+
+  describe "Opening all column families" do
+    setup :tmp_db_path
+
+    test "opens every existing column family", %{path: path} do
+      {:ok, db, %{"people" => people, "places" => places}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["people", "places"])
+
+      :ok = Rox.put(people, "key", "person")
+      :ok = Rox.put(places, "key", "place")
+      :ok = Rox.close(db)
+
+      assert {:ok, _db, cfs} = Rox.open(path, [], :all_column_families)
+      assert ["default", "people", "places"] = cfs |> Map.keys |> Enum.sort
+      assert {:ok, "person"} = Rox.get(cfs["people"], "key")
+      assert {:ok, "place"} = Rox.get(cfs["places"], "key")
+    end
+
+    test "opens the default column family of a new database", %{path: path} do
+      assert {:ok, _db, cfs} = Rox.open(path, [create_if_missing: true], :all_column_families)
+      assert ["default"] = Map.keys(cfs)
+    end
+
+    test "returns the error when the column families can't be listed", %{path: path} do
+      File.mkdir_p!(path)
+      File.write!(Path.join(path, "CURRENT"), "MANIFEST-000404\n")
+
+      assert {:error, reason} = Rox.open(path, [create_if_missing: true], :all_column_families)
+      assert reason =~ "MANIFEST-000404"
+    end
+  end
+
+  describe "Dropping column families" do
+    setup :tmp_db_path
+
+    setup %{path: path} do
+      {:ok, db, %{"retired" => cf}} =
+        Rox.open(path, [create_if_missing: true, auto_create_column_families: true], ["retired"])
+
+      {:ok, %{db: db, cf: cf}}
+    end
+
+    test "removes the column family", %{path: path, db: db, cf: cf} do
+      assert :ok = Rox.put(cf, "key", "val")
+      assert :ok = Rox.drop_cf(db, cf)
+
+      assert {:error, _} = Rox.cf_handle(db, "retired")
+      assert :ok = Rox.close(db)
+      assert {:ok, ["default"]} = Rox.list_cf(path)
+    end
+
+    test "outstanding handles return errors once dropped", %{db: db, cf: cf} do
+      assert :ok = Rox.put(cf, "key", "val")
+      {:ok, snapshot} = Rox.create_snapshot(db)
+      {:ok, snapshot_cf} = Rox.cf_handle(snapshot, "retired")
+
+      assert :ok = Rox.drop_cf(db, "retired")
+
+      assert {:error, :column_family_dropped} = Rox.get(cf, "key")
+      assert {:error, :column_family_dropped} = Rox.put(cf, "key", "val")
+      assert {:error, :column_family_dropped} = Rox.get(snapshot_cf, "key")
+      assert {:error, :column_family_dropped} = Rox.exists?(cf, "key")
+      assert {:error, :column_family_dropped} = Rox.exists?(snapshot_cf, "key")
+      assert {:error, :column_family_dropped} = Rox.stream(cf)
+      assert {:error, :column_family_dropped} = Rox.compact_range(cf)
+      assert {:error, :column_family_dropped} = Rox.compact_range(cf, nil, nil, notify: true)
+      assert {:error, :column_family_dropped} =
+        Batch.new
+        |> Batch.put(cf, "key", "val")
+        |> Batch.write(db)
+    end
+
+    test "a dropped column family can be recreated", %{db: db, cf: cf} do
+      assert :ok = Rox.put(cf, "key", "val")
+      assert :ok = Rox.drop_cf(db, cf)
+
+      assert {:ok, recreated} = Rox.create_cf(db, "retired")
+      assert :not_found = Rox.get(recreated, "key")
+      assert {:error, :column_family_dropped} = Rox.get(cf, "key")
+    end
+  end
+
   describe "Batch Operations" do
     test "puts and deletes", %{db: db, people: people} do
       assert :not_found = Rox.get(db, "batch_put_test")
@@ -171,5 +1173,41 @@ defmodule RoxTest do
       assert :not_found = Rox.get(db, "batch_put_test")
       assert :not_found = Rox.get(people, "batch_put_test")
     end
+
+    test "with write options", %{db: db, people: people} do
+      batch =
+        Batch.new
+        |> Batch.put("batch_write_opts_test", "works")
+        |> Batch.put(people, "batch_write_opts_test", "works")
+
+      assert :ok = Batch.write(batch, db, sync: true)
+      assert :ok = Batch.write(batch, db, low_pri: true, no_slowdown: true)
+      assert {:ok, "works"} = Rox.get(db, "batch_write_opts_test")
+      assert {:ok, "works"} = Rox.get(people, "batch_write_opts_test")
+
+      assert {:error, {:invalid_option, :synk, :unknown_option}} = Batch.write(batch, db, synk: true)
+      assert {:error, {:invalid_option, :sync, :invalid_value}} = Batch.write(batch, db, sync: 1)
+    end
+  end
+
+  defp tmp_db_path(%{test: test}) do
+    path = Path.join(__DIR__, "#{test}.rocksdb")
+    on_exit fn -> File.rm_rf(path) end
+
+    {:ok, %{path: path}}
+  end
+
+  # Returns the `[CFOptions "name"]` section of the newest OPTIONS file RocksDB persisted in `path`
+  defp persisted_cf_options(path, cf_name) do
+    [_, section] =
+      Path.join(path, "OPTIONS-*")
+      |> Path.wildcard
+      |> Enum.max
+      |> File.read!
+      |> String.split("[CFOptions \"#{cf_name}\"]", parts: 2)
+
+    section
+    |> String.split("\n[", parts: 2)
+    |> hd
   end
 end